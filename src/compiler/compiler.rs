@@ -1,14 +1,46 @@
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{Write, stdout};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::env;
 
-use compiler::SourceFile;
-use linker::Linker;
+use compiler::{Diagnostic, Diagnostics, ErrorCode, Logger, Include, LogLevel, Region, Section, SourceFile, SourceString, Symbol, SymbolKind, SymbolTable, Warning, label_name, relative_path};
+use compiler::dump::render_dump;
+use compiler::hex::render_hex;
+use compiler::lines::render_line_table;
+use compiler::listing::render_listing;
+use compiler::mapping::render_map;
+use compiler::render::{render_diagnostic, render_diagnostic_json};
+use linker::{Archive, ArchiveMember, Linker, ObjectFile, ObjectSource, create_bps, create_ips};
+use parser::{BaseLexer, Expression, Span, Token};
+
+/// The number of errors after which compilation stops by default
+const DEFAULT_ERROR_LIMIT: usize = 20;
+
+/// How deep INCLUDE directives can be nested
+const MAX_INCLUDE_DEPTH: usize = 16;
 
 pub struct Compiler<'sf> {
     files: Vec<SourceFile<'sf>>,
     base_path: PathBuf,
-    silent: bool,
-    verbose: bool
+    symbols: SymbolTable,
+    sections: Vec<Section>,
+    diagnostics: Diagnostics,
+    build_time: u64,
+    logger: Logger,
+
+    /// Only parse the sources without linking them, e.g. for object files
+    compile_only: bool,
+
+    /// The IDs of all files which were loaded from object files
+    object_files: Vec<usize>,
+
+    /// The paths of all files which were read during compilation
+    dependencies: Vec<String>,
+
+    /// The path of the ROM image the sections are assembled into and its
+    /// contents
+    base_rom: Option<(String, Vec<u8>)>
 }
 
 impl<'sf> Compiler<'sf> {
@@ -17,87 +49,802 @@ impl<'sf> Compiler<'sf> {
         Compiler {
             files: vec![],
             base_path: PathBuf::new(),
-            silent: silent,
-            verbose: verbose
+            symbols: SymbolTable::new(),
+            sections: vec![],
+            diagnostics: Diagnostics::new(DEFAULT_ERROR_LIMIT),
+            build_time: match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(duration) => duration.as_secs(),
+                Err(_) => 0
+            },
+            logger: Logger::new(if silent {
+                LogLevel::Silent
+
+            } else if verbose {
+                LogLevel::Verbose
+
+            } else {
+                LogLevel::Normal
+            }),
+            compile_only: false,
+            object_files: vec![],
+            dependencies: vec![],
+            base_rom: None
         }
     }
 
-    pub fn compile_source_files(&mut self, files: &Vec<&str>, verify: bool) -> Result<bool, &str> {
+    /// Parses and links all source files, in case of errors all reported
+    /// error diagnostics are returned
+    pub fn compile_source_files(&mut self, files: &Vec<&str>, verify: bool) -> Result<(), Vec<Diagnostic>> {
 
-        // Clear any existing source files
+        // Clear any existing source files, sections and diagnostics
         self.files.clear();
+        self.object_files.clear();
+        self.dependencies.clear();
+        self.sections.clear();
+        self.diagnostics.clear();
 
         // Set base directory from first source file
         self.base_path = env::current_dir().unwrap();
         self.base_path.push(files.get(0).unwrap());
         self.base_path.set_file_name("");
 
-        println!("Compiling sources: {:?} (Base: {:?})", files, self.base_path);
+        self.logger.verbose(&format!("Compiling {} source file(s) from {:?}", files.len(), self.base_path));
+        let started = Instant::now();
+
+        // Setup built-in symbols for build metadata
+        let (date, time) = format_timestamp(self.build_time);
+        self.symbols.set_builtin("__VERSION__", Expression::String(crate_version!(), Span::default()));
+        self.symbols.set_builtin("__DATE__", Expression::String(date, Span::default()));
+        self.symbols.set_builtin("__TIME__", Expression::String(time, Span::default()));
 
         // Parse and link files
+        let parsing = Instant::now();
         self.parse_files(files);
-        self.link_files(verify);
+        self.check_unused_symbols();
+        self.logger.timing(&format!("Parsing {} file(s)", self.files.len()), parsing);
+
+        if self.errors().is_empty() && !self.compile_only {
+            let linking = Instant::now();
+            self.link_files(verify);
+            self.logger.timing("Linking", linking);
+        }
+
+        let errors = self.errors();
+        if errors.is_empty() {
+            self.logger.timing("Compilation", started);
+            Ok(())
+
+        } else {
+            self.logger.verbose(&format!("Compilation failed with {} error(s)", errors.len()));
+            Err(errors)
+        }
+
+    }
+
+    /// Returns all diagnostics which were reported during the last compilation
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.diagnostics.as_slice()
+    }
+
+    /// Sets the maximum number of errors which are reported before
+    /// compilation stops, 0 disables the limit
+    pub fn set_error_limit(&mut self, limit: usize) {
+        self.diagnostics.set_error_limit(limit);
+    }
+
+    /// Enables or disables warnings via their name ("unused"), "no-" prefixed
+    /// name ("no-unused"), "all", or turns them into errors ("error")
+    pub fn configure_warning(&mut self, option: &str) -> Result<(), String> {
+
+        let (name, enabled) = match option.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (option, true)
+        };
+
+        if name == "error" {
+            self.diagnostics.set_warnings_as_errors(enabled);
+
+        } else if name == "all" {
+            for warning in Warning::all() {
+                self.diagnostics.set_warning(*warning, enabled);
+            }
+
+        } else {
+            match Warning::from_name(name) {
+                Some(warning) => self.diagnostics.set_warning(warning, enabled),
+                None => return Err(format!("Unknown warning \"{}\"", name))
+            }
+        }
 
-        Ok(true)
+        Ok(())
+
+    }
+
+    /// Renders a diagnostic together with the source lines it refers to,
+    /// using ANSI colors if requested
+    pub fn format_diagnostic(&self, diagnostic: &Diagnostic, color: bool) -> String {
+        render_diagnostic(diagnostic, &self.files, color)
+    }
+
+    /// Renders a diagnostic as a single line of JSON
+    pub fn format_diagnostic_json(&self, diagnostic: &Diagnostic) -> String {
+        render_diagnostic_json(diagnostic, &self.files)
+    }
+
+    /// Only parses the sources without linking them so their sections and
+    /// symbols can be written into a object file
+    pub fn set_compile_only(&mut self, compile_only: bool) {
+        self.compile_only = compile_only;
+    }
+
+    /// Overrides the build time (in seconds since the unix epoch) which is
+    /// exposed via the __DATE__ and __TIME__ symbols
+    pub fn set_build_time(&mut self, timestamp: u64) {
+        self.build_time = timestamp;
+    }
+
+    /// Defines a constant symbol before any source files are parsed, the value
+    /// is evaluated as a expression against all previously defined symbols
+    pub fn define_symbol(&mut self, name: &str, value: &str) -> Result<(), Diagnostic> {
+
+        // Only allow plain names which could also be defined from source
+        match &lex_source("name", name)[..] {
+            [(Token::Name(_), _)] => {},
+            _ => return Err(Diagnostic::error(
+                ErrorCode::InvalidSymbolName,
+                format!("Invalid symbol name \"{}\"", name),
+                Span::default()
+            ))
+        }
+
+        // Wrap the value in parenthesis for the expression parser
+        let mut tokens = vec![(Token::LParen, Span::default())];
+        for (token, span) in lex_source("value", value) {
+            match token {
+                Token::Number(_) | Token::String(_) | Token::Name(_) |
+                Token::Operator(_) | Token::LParen | Token::RParen => tokens.push((token, span)),
+                Token::Error(diagnostic) => return Err(diagnostic.with_span(Span::default())),
+                _ => return Err(Diagnostic::error(
+                    ErrorCode::ExpectedValue,
+                    format!("Invalid value \"{}\" for symbol \"{}\"", value, name),
+                    Span::default()
+                ))
+            }
+        }
+        tokens.push((Token::RParen, Span::default()));
+
+        let symbols = &mut self.symbols;
+        match Expression::new(tokens).evaluate(&mut |n| symbols.resolve(n)) {
+            Expression::Invalid(diagnostic) => Err(diagnostic.with_span(Span::default())),
+            value => symbols.define(name, SymbolKind::Constant, value.with_span(Span::default()), Span::default())
+        }
 
     }
 
     pub fn optimize_instructions(&mut self, allow_unsafe: bool) {
+        let started = Instant::now();
+        self.logger.info(&format!("Optimizing instructions (unsafe={})", allow_unsafe));
         Linker::optimize(allow_unsafe);
+        self.logger.timing("Optimizing", started);
     }
 
-    pub fn report_unsused(&self) -> &str {
-        "No unused variables or labels."
+    /// Lists all symbols which were neither referenced nor exported together
+    /// with their definition location
+    pub fn report_unused(&self) -> String {
+
+        let unused = self.unused_symbols();
+        if unused.is_empty() {
+            return "No unused symbols.".to_string();
+        }
+
+        let mut report = format!("{} unused symbol(s):", unused.len());
+        for (name, symbol) in unused {
+            let kind = match symbol.kind {
+                SymbolKind::Label if name.contains('.') => "local label",
+                SymbolKind::Label => "global label",
+                kind => kind.name()
+            };
+            let filename = self.files.get(symbol.span.file).map_or("?".to_string(), |f| f.display_path());
+            report.push_str(&format!(
+                "\n  {}:{}:{} {} \"{}\"",
+                filename, symbol.span.line, symbol.span.column, kind, name
+            ));
+        }
+
+        report
+
     }
 
-    pub fn generate_rom_image(&self, file: &str) {
-        self.write_out(file, "ROM");
+    /// Writes all parsed sections and symbols together with their sources
+    /// into a object file which can be linked later on
+    pub fn generate_object_file(&self, file: &str) -> Result<(), String> {
+
+        let sources = self.files.iter().map(|f| ObjectSource {
+            path: f.path.clone(),
+            filename: f.filename.clone(),
+            source: f.source().to_vec()
+
+        }).collect();
+
+        // Command line definitions and macros are not part of the object
+        let mut symbols: Vec<_> = self.symbols.symbols().into_iter().filter(|&(_, symbol)| {
+            symbol.span != Span::default() && symbol.kind != SymbolKind::Macro
+
+        }).map(|(name, symbol)| (name.clone(), symbol.clone())).collect();
+        symbols.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut exports: Vec<String> = self.symbols.exports().into_iter().cloned().collect();
+        exports.sort();
+
+        let object = ObjectFile {
+            sources,
+            sections: self.sections.clone(),
+            symbols,
+            exports
+        };
+        self.write_out(file, "OBJECT", &object.to_bytes())
+
     }
 
-    pub fn generate_symbol_file(&self, file: &str) {
-        self.write_out(file, "SYMBOLS");
+    /// Bundles object files into a archive from which the linker only loads
+    /// the members that define otherwise undefined names
+    pub fn generate_archive(&self, file: &str, objects: &[&str]) -> Result<(), String> {
+
+        let mut members = vec![];
+        for path in objects {
+            let bytes = fs::read(path).map_err(|err| format!("Failed to open file \"{}\": {}", path, err))?;
+            members.push(ArchiveMember {
+                name: PathBuf::from(path).file_name().and_then(|n| n.to_str()).unwrap_or(path).to_string(),
+                bytes
+            });
+        }
+
+        let archive = Archive::new(members)?;
+        self.write_out(file, "ARCHIVE", &archive.to_bytes())
+
     }
 
-    pub fn generate_mapping_file(&self, file: &str) {
-        self.write_out(file, "MAPPING");
+    /// Writes a Makefile rule which makes the targets depend on all files that
+    /// were read, with a empty rule for each of them so deleted files do not
+    /// break the build
+    pub fn generate_dependency_file(&self, file: &str, targets: &[String]) -> Result<(), String> {
+
+        let mut dependencies: Vec<String> = vec![];
+        for path in self.dependencies.iter().chain(self.base_rom.iter().map(|base| &base.0)) {
+            let path = escape_make_path(path);
+            if !dependencies.contains(&path) {
+                dependencies.push(path);
+            }
+        }
+
+        // Output written to standard out is not a file make could check
+        let targets: Vec<String> = targets.iter().filter(|t| *t != "stdout").map(|t| escape_make_path(t)).collect();
+        let mut rule = format!("{}:", targets.join(" "));
+        for path in &dependencies {
+            rule.push_str(&format!(" \\\n  {}", path));
+        }
+        rule.push('\n');
+
+        for path in &dependencies {
+            rule.push_str(&format!("\n{}:\n", path));
+        }
+
+        self.write_out(file, "DEPENDENCIES", rule.as_bytes())
+
+    }
+
+    /// Loads a existing ROM image which all sections are assembled into
+    /// instead of a empty one, it must be loaded before compilation so
+    /// sections can be placed around its contents
+    pub fn load_base_rom(&mut self, file: &str) -> Result<(), String> {
+        let bytes = fs::read(file).map_err(|err| {
+            format!("Failed to read base ROM \"{}\": {}", file, err)
+        })?;
+        self.logger.verbose(&format!("Loaded base ROM \"{}\" ({} bytes)", file, bytes.len()));
+        self.base_rom = Some((relative_path(Path::new(file)), bytes));
+        Ok(())
+    }
+
+    pub fn generate_rom_image(&self, file: &str) -> Result<(), String> {
+        self.write_out(file, "ROM", &self.rom_image())
+    }
+
+    /// Writes a IPS patch from the base ROM to the assembled image
+    pub fn generate_ips_patch(&self, file: &str) -> Result<(), String> {
+        let base = &self.base_rom.as_ref().ok_or("IPS patches require a base ROM")?.1;
+        self.write_out(file, "IPS patch", &create_ips(base, &self.rom_image())?)
     }
 
-    pub fn generate_json_file(&self, file: &str) {
-        self.write_out(file, "JSON DUMP");
+    /// Writes a BPS patch from the base ROM to the assembled image
+    pub fn generate_bps_patch(&self, file: &str) -> Result<(), String> {
+        let base = &self.base_rom.as_ref().ok_or("BPS patches require a base ROM")?.1;
+        self.write_out(file, "BPS patch", &create_bps(base, &self.rom_image())?)
+    }
+
+    /// Writes the ROM image as Intel HEX records
+    pub fn generate_hex_file(&self, file: &str) -> Result<(), String> {
+        self.write_out(file, "HEX", render_hex(&self.rom_image()).as_bytes())
+    }
+
+    /// Writes every 16KB bank of the ROM image into its own file, the bank
+    /// number is inserted before the extension e.g. "game.01.gb", the names of
+    /// all written files are returned
+    pub fn generate_bank_files(&self, file: &str) -> Result<Vec<String>, String> {
+        let path = Path::new(file);
+        let stem = path.with_extension("");
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| format!(".{}", e)).unwrap_or_default();
+        let mut files = vec![];
+        for (bank, bytes) in self.rom_image().chunks(0x4000).enumerate() {
+            let name = format!("{}.{:0>2X}{}", stem.display(), bank, extension);
+            self.write_out(&name, &format!("ROM bank {}", bank), bytes)?;
+            files.push(name);
+        }
+        Ok(files)
+    }
+
+    /// Writes all labels as "bank:address name" lines sorted by their
+    /// location, optionally followed by all numeric constants and variables
+    pub fn generate_symbol_file(&self, file: &str, constants: bool) -> Result<(), String> {
+
+        let mut labels: Vec<(usize, usize, &str)> = self.sections.iter().flat_map(|section| {
+            section.labels().into_iter().map(move |(name, address, _)| (section.bank, address, label_name(name)))
+
+        }).collect();
+        labels.sort_by_key(|&(bank, address, _)| (bank, address));
+
+        let mut lines = vec!["; File generated by gbasm".to_string()];
+        for (bank, address, name) in labels {
+            lines.push(format!("{:0>2X}:{:0>4X} {}", bank, address, name));
+        }
+
+        if constants {
+            for &(kind, title) in &[(SymbolKind::Constant, "; Constants"), (SymbolKind::Variable, "; Variables")] {
+
+                let mut values: Vec<(usize, &str)> = self.symbols.symbols().into_iter().filter_map(|(name, symbol)| {
+                    match symbol.value {
+                        Expression::Number(value, _) if symbol.kind == kind && (0.0..=65535.0).contains(&value) => {
+                            Some((value as usize, &name[..]))
+                        },
+                        _ => None
+                    }
+
+                }).collect();
+                values.sort();
+
+                lines.push(title.to_string());
+                for (value, name) in values {
+                    lines.push(format!("00:{:0>4X} {}", value, name));
+                }
+
+            }
+        }
+
+        lines.push(String::new());
+        self.write_out(file, "SYMBOLS", lines.join("\n").as_bytes())
+
+    }
+
+    /// Writes a overview of all regions and banks with their sections and
+    /// free space
+    pub fn generate_mapping_file(&self, file: &str) -> Result<(), String> {
+        let map = render_map(&self.sections, Linker::rom_banks(&self.sections));
+        self.write_out(file, "MAPPING", map.as_bytes())
+    }
+
+    /// Writes every source line next to the bank, address and bytes it
+    /// assembled to
+    pub fn generate_listing_file(&self, file: &str) -> Result<(), String> {
+        let listing = render_listing(&self.sections, &self.files);
+        self.write_out(file, "LISTING", listing.as_bytes())
+    }
+
+    /// Writes the table of ROM byte ranges and the source lines they were
+    /// assembled from, see `render_line_table` for the format
+    pub fn generate_line_table(&self, file: &str) -> Result<(), String> {
+        let table = render_line_table(&self.sections, &self.files);
+        self.write_out(file, "LINE TABLE", table.as_bytes())
+    }
+
+    /// Writes all sections, labels, constants and instructions as JSON, see
+    /// `render_dump` for the format
+    pub fn generate_json_file(&self, file: &str) -> Result<(), String> {
+        let dump = render_dump(&self.sections, &self.symbols, &self.files);
+        self.write_out(file, "JSON DUMP", format!("{}\n", dump).as_bytes())
     }
 
     // Private ----------------------------------------------------------------
 
     fn parse_files(&mut self, files: &Vec<&str>) {
+
+        let mut archives = vec![];
         for s in files {
-            self.include_file(s);
+            if self.diagnostics.limit_reached() {
+                break;
+
+            } else if s.ends_with(".a") {
+                match self.read_file(s).and_then(|bytes| Archive::from_bytes(&bytes).map_err(|err| Diagnostic::error(
+                    ErrorCode::InvalidObjectFile,
+                    format!("Failed to read archive \"{}\": {}", s, err),
+                    Span::default()
+                ))) {
+                    Ok(archive) => archives.push((*s, archive)),
+                    Err(diagnostic) => self.diagnostics.report(diagnostic)
+                }
+
+            } else if s.ends_with(".o") {
+                if let Err(diagnostic) = self.load_object_file(s) {
+                    self.diagnostics.report(diagnostic);
+                }
+
+            } else if let Err(diagnostic) = self.include_file(s) {
+                self.diagnostics.report(diagnostic);
+            }
         }
+
+        self.link_archive_members(&archives);
+        self.check_deferred_constants();
+
     }
 
-    fn include_file(&mut self, path: &str) -> Result<&SourceFile<'sf>, String> {
-        match SourceFile::new(None, self.base_path.join(path)) {
-            Ok(file) => {
-                println!("Including file \"{}\"", path);
-                self.files.push(file);
-                let source_file = self.files.last_mut().unwrap();
-                source_file.parse();
-                Ok(source_file)
-            },
-            Err(err) => Err(err)
+    /// Loads archive members as long as they define names which are
+    /// referenced but not defined yet
+    fn link_archive_members(&mut self, archives: &[(&str, Archive)]) {
+
+        let mut loaded = vec![];
+        while !self.diagnostics.limit_reached() {
+
+            // Constants like "X EQU engine_init + 1" can refer to names which
+            // are only defined by a archive member as well
+            let mut references: Vec<String> = self.sections.iter().flat_map(|s| s.references()).collect();
+            for (_, symbol) in self.symbols.symbols() {
+                symbol.value.visit_names(&mut |n| references.push(n.to_string()));
+            }
+
+            let undefined: Vec<String> = references.into_iter().filter(|name| {
+                self.symbols.get(name).is_none()
+
+            }).collect();
+
+            let member = undefined.iter().filter_map(|name| {
+                archives.iter().enumerate().filter_map(|(a, (_, archive))| {
+                    archive.find(name).map(|m| (a, m))
+
+                }).find(|member| !loaded.contains(member))
+
+            }).next();
+
+            match member {
+                Some((a, m)) => {
+                    loaded.push((a, m));
+                    let (path, ref archive) = archives[a];
+                    let member = &archive.members[m];
+                    let name = format!("{}({})", path, member.name);
+                    if let Err(diagnostic) = self.load_object(&name, &member.bytes) {
+                        self.diagnostics.report(diagnostic);
+                    }
+                },
+                None => break
+            }
+
+        }
+
+    }
+
+    /// Reports constants of the parsed sources which refer to labels or to
+    /// names which are neither defined before them nor by any object file
+    fn check_deferred_constants(&mut self) {
+
+        let mut constants: Vec<_> = self.symbols.symbols().into_iter().filter(|&(_, symbol)| {
+            symbol.kind == SymbolKind::Constant && symbol.span != Span::default() &&
+            !self.object_files.contains(&symbol.span.file) &&
+            !matches!(symbol.value, Expression::Number(_, _) | Expression::String(_, _) | Expression::Invalid(_))
+
+        }).map(|(_, symbol)| symbol.clone()).collect();
+        constants.sort_by_key(|symbol| (symbol.span.file, symbol.span.start));
+
+        for constant in constants {
+            let symbols = &self.symbols;
+            let object_files = &self.object_files;
+            let compile_only = self.compile_only;
+            let value = constant.value.evaluate(&mut |n| match symbols.get(n) {
+                Some(symbol) if object_files.contains(&symbol.span.file) => Some(Expression::Number(0.0, Span::default())),
+                Some(symbol) if symbol.kind == SymbolKind::Label || symbol.kind == SymbolKind::Macro => Some(symbol.value.clone()),
+                Some(_) => None,
+                None if compile_only => Some(Expression::Number(0.0, Span::default())),
+                None => None
+            });
+            if let Expression::Invalid(diagnostic) = value {
+                self.diagnostics.report(diagnostic);
+            }
+        }
+
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, Diagnostic> {
+        self.dependencies.push(relative_path(Path::new(path)));
+        fs::read(path).map_err(|err| Diagnostic::error(
+            ErrorCode::FileNotFound,
+            format!("Failed to open file \"{}\": {}", path, err),
+            Span::default()
+        ))
+    }
+
+    fn load_object_file(&mut self, path: &str) -> Result<(), Diagnostic> {
+        let bytes = self.read_file(path)?;
+        self.load_object(path, &bytes)
+    }
+
+    fn load_object(&mut self, path: &str, bytes: &[u8]) -> Result<(), Diagnostic> {
+
+        let object = ObjectFile::from_bytes(bytes, self.files.len()).map_err(|err| Diagnostic::error(
+            ErrorCode::InvalidObjectFile,
+            format!("Failed to read object file \"{}\": {}", path, err),
+            Span::default()
+        ))?;
+
+        self.logger.verbose(&format!("Loading object file \"{}\"", path));
+        for source in object.sources {
+            let mut file = SourceFile::from_source(source.path, source.filename, source.source);
+            file.id = self.files.len();
+            self.object_files.push(file.id);
+            self.files.push(file);
+        }
+
+        for section in object.sections {
+            if let Some(previous) = self.sections.iter().find(|s| s.name == section.name) {
+                self.diagnostics.report(Diagnostic::error(
+                    ErrorCode::SectionRedefinition,
+                    format!("Section \"{}\" is already defined", section.name),
+                    section.span
+
+                ).with_label(previous.span, "previously defined here"));
+
+            } else {
+                self.sections.push(section);
+            }
+        }
+
+        for (name, symbol) in object.symbols {
+
+            // Constants from a shared include file are part of every object
+            // which included it
+            let shared = self.symbols.get(&name).is_some_and(|existing| {
+                existing.kind == SymbolKind::Constant && symbol.kind == SymbolKind::Constant &&
+                existing.value.clone().with_span(Span::default()) == symbol.value.clone().with_span(Span::default())
+            });
+
+            if !shared {
+                let result = match symbol.kind {
+                    SymbolKind::Constant => self.symbols.define_deferred(&name, &symbol.value, symbol.span),
+                    kind => self.symbols.define(&name, kind, symbol.value, symbol.span)
+                };
+                if let Err(diagnostic) = result {
+                    self.diagnostics.report(diagnostic);
+                }
+            }
+
+        }
+
+        for name in object.exports {
+            self.symbols.export(&name);
+        }
+
+        Ok(())
+
+    }
+
+    fn include_file(&mut self, path: &str) -> Result<(), Diagnostic> {
+
+        // Source files from the command line are relative to the current
+        // directory, only INCLUDE directives use the base path
+        let path = env::current_dir().unwrap().join(path);
+        let mut includer = Includer {
+            files: &mut self.files,
+            dependencies: &mut self.dependencies,
+            base_path: &self.base_path,
+            logger: &self.logger,
+            depth: 0
+        };
+        includer.include(&path.to_string_lossy(), Span::default(), &mut self.symbols, &mut self.diagnostics, &mut self.sections, None).map(|_| ())
+
+    }
+
+    fn check_unused_symbols(&mut self) {
+
+        let warnings: Vec<Diagnostic> = self.unused_symbols().into_iter().map(|(name, symbol)| {
+            Diagnostic::warning(
+                Warning::Unused,
+                format!("Unused {} \"{}\"", symbol.kind.name(), name),
+                symbol.span
+            )
+
+        }).collect();
+
+        for warning in warnings {
+            self.diagnostics.report(warning);
+        }
+
+    }
+
+    /// Returns all unused symbols ordered by their definition location
+    fn unused_symbols(&self) -> Vec<(&String, &Symbol)> {
+
+        // Symbols from the command line are allowed to be unused, symbols
+        // from object files were already checked when they were compiled
+        let mut unused: Vec<_> = self.symbols.unused().into_iter().filter(|&(_, symbol)| {
+            symbol.span != Span::default() && !self.object_files.contains(&symbol.span.file)
+
+        }).collect();
+        unused.sort_by_key(|&(_, symbol)| (symbol.span.file, symbol.span.start));
+        unused
+
+    }
+
+    fn link_files(&mut self, _verify: bool) {
+
+        let base = self.base_rom.as_ref().map_or(&[][..], |base| &base.1[..]);
+        Linker::link(&mut self.sections, base, &mut self.symbols, &mut self.diagnostics);
+
+        for section in &self.sections {
+            self.logger.verbose(&format!(
+                "Section \"{}\" in {}[{}] at ${:0>4X}-${:0>4X} ({} bytes)",
+                section.name, section.region.name(), section.bank, section.address,
+                (section.address + section.size).max(section.address + 1) - 1, section.size
+            ));
+        }
+
+        for region in Region::all() {
+            let used: usize = self.sections.iter().filter(|s| s.region == *region).map(|s| s.size).sum();
+            if used > 0 {
+                self.logger.verbose(&format!("{} uses {} bytes", region.name(), used));
+            }
         }
+
     }
 
-    fn link_files(&mut self, verify: bool) {
-        Linker::init_files(&mut self.files);
-        Linker::link_files(&mut self.files);
+    fn rom_image(&self) -> Vec<u8> {
+        match self.base_rom {
+            Some((_, ref base)) => Linker::patch_image(base, &self.sections),
+            None => Linker::rom_image(&self.sections)
+        }
     }
 
-    fn write_out(&self, file: &str, content: &str) {
+    fn errors(&self) -> Vec<Diagnostic> {
+        self.diagnostics.as_slice().iter().filter(|d| d.is_error()).cloned().collect()
+    }
+
+    fn write_out(&self, file: &str, content: &str, bytes: &[u8]) -> Result<(), String> {
         match file {
-            "stdout" => println!("Output {} to standard out", content),
-            file => println!("Output {} to file \"{}\"", content, file)
+            "stdout" => {
+                self.logger.info(&format!("Output {} to standard out", content));
+                stdout().write_all(bytes).map_err(|err| format!("Failed to write {} to standard out: {}", content, err))
+            },
+            file => {
+                self.logger.info(&format!("Output {} to file \"{}\"", content, file));
+                File::create(file).and_then(|mut f| f.write_all(bytes)).map_err(|err| {
+                    format!("Failed to write {} to file \"{}\": {}", content, file, err)
+                })
+            }
         }
     }
 
 }
 
+
+/// Parses the source files which are included by other files
+struct Includer<'c, 'sf: 'c> {
+    files: &'c mut Vec<SourceFile<'sf>>,
+    dependencies: &'c mut Vec<String>,
+    base_path: &'c Path,
+    logger: &'c Logger,
+    depth: usize
+}
+
+impl<'c, 'sf> Include for Includer<'c, 'sf> {
+
+    fn include(
+        &mut self,
+        path: &str,
+        span: Span,
+        symbols: &mut SymbolTable,
+        diagnostics: &mut Diagnostics,
+        sections: &mut Vec<Section>,
+        section: Option<usize>
+
+    ) -> Result<Option<usize>, Diagnostic> {
+
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(Diagnostic::error(
+                ErrorCode::IncludeDepth,
+                format!("Cannot include \"{}\", INCLUDE directives are nested too deep", path),
+                span
+            ));
+        }
+
+        let path = self.base_path.join(path);
+        let mut file = SourceFile::new(None, path.clone()).map_err(|err| {
+            Diagnostic::error(ErrorCode::FileNotFound, err, span)
+        })?;
+
+        self.logger.verbose(&format!("Including file \"{}\"", relative_path(&path)));
+        self.dependencies.push(relative_path(&path));
+
+        // The slot of the file is reserved so spans can refer to it while it
+        // is being parsed
+        file.id = self.files.len();
+        file.included_from = if span == Span::default() { None } else { Some(span) };
+        self.files.push(SourceFile::from_source(file.path.clone(), file.filename.clone(), vec![]));
+
+        let logger = self.logger;
+        self.depth += 1;
+        let section = file.parse(symbols, diagnostics, sections, section, logger, self);
+        self.depth -= 1;
+
+        let id = file.id;
+        self.files[id] = file;
+        Ok(section)
+
+    }
+
+    fn include_binary(&mut self, path: &str, span: Span) -> Result<Vec<u8>, Diagnostic> {
+        self.dependencies.push(relative_path(&self.base_path.join(path)));
+        fs::read(self.base_path.join(path)).map_err(|err| Diagnostic::error(
+            ErrorCode::FileNotFound,
+            format!("Failed to open file \"{}\": {}", path, err),
+            span
+        ))
+    }
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn lex_source(path: &str, source: &str) -> Vec<(Token, Span)> {
+
+    let mut source = SourceString::new(path, source);
+    let mut tokens = vec![];
+
+    for (token, span) in BaseLexer::new(&mut source) {
+        match token {
+            Token::Eof => break,
+            Token::Error(_) => {
+                tokens.push((token, span));
+                break;
+            },
+            token => tokens.push((token, span))
+        }
+    }
+
+    tokens
+
+}
+
+/// Escapes spaces and other characters which are special in Makefile rules
+fn escape_make_path(path: &str) -> String {
+    path.replace('$', "$$").replace('#', "\\#").replace(' ', "\\ ")
+}
+
+/// Formats a unix timestamp into a UTC (YYYY-MM-DD, HH:MM:SS) pair
+fn format_timestamp(timestamp: u64) -> (String, String) {
+
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Convert days since the epoch into a civil date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    )
+
+}
+
@@ -0,0 +1,571 @@
+use std::fmt;
+
+use parser::Span;
+
+/// How severe a diagnostic is
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+/// Warnings which can be enabled and disabled by their name
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Warning {
+    /// A value which does not fit into its DB or DW storage
+    Truncation,
+    /// A local label which replaces a earlier one within the same scope
+    Shadow,
+    /// A constant or label which is never referenced
+    Unused,
+    /// Syntax which is only supported for compatibility
+    Deprecated,
+    /// Instructions which directly follow a unconditional jump or return
+    Unreachable,
+    /// A section which replaces bytes of the base ROM which are in use
+    Overwrite
+}
+
+const WARNINGS: [Warning; 6] = [
+    Warning::Truncation,
+    Warning::Shadow,
+    Warning::Unused,
+    Warning::Deprecated,
+    Warning::Unreachable,
+    Warning::Overwrite
+];
+
+impl Warning {
+
+    /// Returns all warnings in the order of their codes
+    pub fn all() -> &'static [Warning] {
+        &WARNINGS
+    }
+
+    pub fn from_name(name: &str) -> Option<Warning> {
+        WARNINGS.iter().find(|w| w.name() == name).cloned()
+    }
+
+    /// Returns the name used to enable or disable the warning
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Warning::Truncation => "truncation",
+            Warning::Shadow => "shadow",
+            Warning::Unused => "unused",
+            Warning::Deprecated => "deprecated",
+            Warning::Unreachable => "unreachable",
+            Warning::Overwrite => "overwrite"
+        }
+    }
+
+    /// Whether the warning is reported without explicitly enabling it
+    pub fn is_default(&self) -> bool {
+        *self != Warning::Unused
+    }
+
+}
+
+/// Stable codes for all errors and warnings the assembler can report
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ErrorCode {
+
+    // Lexing
+    UnexpectedCharacter,
+    InvalidEscapeSequence,
+    UnclosedString,
+    InvalidStringContents,
+    InvalidOperator,
+    NumberTooLong,
+    EmptyName,
+    UnclosedInterpolation,
+    InvalidOffset,
+
+    // Parsing
+    ExpectedOffsetNumber,
+    UnexpectedMacroArg,
+    NestedMacroArgs,
+    ExpectedMacroName,
+    UnexpectedMacroEnd,
+    UnexpectedConditionalElse,
+    IncompleteConditional,
+    InvalidCall,
+    InvalidUnaryOperator,
+    ExpectedValue,
+    UnbalancedParenthesis,
+    MissingOperand,
+    UnexpectedToken,
+    LocalLabelWithoutScope,
+    UnexpectedNoWarnEnd,
+    UnknownWarning,
+    UnclosedMacro,
+    InvalidInstruction,
+    UnknownMacro,
+    MacroArguments,
+    ExpansionDepth,
+    UnclosedRepeat,
+    UnexpectedRepeatEnd,
+
+    // Evaluation
+    UndefinedName,
+    UnknownFunction,
+    InvalidOperands,
+    DivisionByZero,
+    InvalidFormat,
+    UnresolvedLabel,
+    MacroAsValue,
+    OperandOutOfRange,
+
+    // Symbols
+    BuiltinRedefinition,
+    ConstantRedefinition,
+    VariableRedefinition,
+    ExpectedString,
+    InvalidSymbolName,
+    LabelRedefinition,
+    MacroRedefinition,
+
+    // Files
+    FileNotFound,
+    InvalidObjectFile,
+    IncludeDepth,
+
+    // Sections
+    CodeOutsideSection,
+    InvalidSection,
+    SectionRedefinition,
+    SectionOverflow,
+    SectionOverlap,
+    DataInRamSection,
+
+    Warning(Warning)
+
+}
+
+/// All error codes, warnings are listed separately
+const ERROR_CODES: [ErrorCode; 56] = [
+    ErrorCode::UnexpectedCharacter,
+    ErrorCode::InvalidEscapeSequence,
+    ErrorCode::UnclosedString,
+    ErrorCode::InvalidStringContents,
+    ErrorCode::InvalidOperator,
+    ErrorCode::NumberTooLong,
+    ErrorCode::EmptyName,
+    ErrorCode::UnclosedInterpolation,
+    ErrorCode::InvalidOffset,
+    ErrorCode::ExpectedOffsetNumber,
+    ErrorCode::UnexpectedMacroArg,
+    ErrorCode::NestedMacroArgs,
+    ErrorCode::ExpectedMacroName,
+    ErrorCode::UnexpectedMacroEnd,
+    ErrorCode::UnexpectedConditionalElse,
+    ErrorCode::IncompleteConditional,
+    ErrorCode::InvalidCall,
+    ErrorCode::InvalidUnaryOperator,
+    ErrorCode::ExpectedValue,
+    ErrorCode::UnbalancedParenthesis,
+    ErrorCode::MissingOperand,
+    ErrorCode::UnexpectedToken,
+    ErrorCode::LocalLabelWithoutScope,
+    ErrorCode::UnexpectedNoWarnEnd,
+    ErrorCode::UnknownWarning,
+    ErrorCode::UnclosedMacro,
+    ErrorCode::InvalidInstruction,
+    ErrorCode::UnknownMacro,
+    ErrorCode::MacroArguments,
+    ErrorCode::ExpansionDepth,
+    ErrorCode::UnclosedRepeat,
+    ErrorCode::UnexpectedRepeatEnd,
+    ErrorCode::UndefinedName,
+    ErrorCode::UnknownFunction,
+    ErrorCode::InvalidOperands,
+    ErrorCode::DivisionByZero,
+    ErrorCode::InvalidFormat,
+    ErrorCode::UnresolvedLabel,
+    ErrorCode::MacroAsValue,
+    ErrorCode::OperandOutOfRange,
+    ErrorCode::BuiltinRedefinition,
+    ErrorCode::ConstantRedefinition,
+    ErrorCode::VariableRedefinition,
+    ErrorCode::ExpectedString,
+    ErrorCode::InvalidSymbolName,
+    ErrorCode::LabelRedefinition,
+    ErrorCode::MacroRedefinition,
+    ErrorCode::FileNotFound,
+    ErrorCode::InvalidObjectFile,
+    ErrorCode::IncludeDepth,
+    ErrorCode::CodeOutsideSection,
+    ErrorCode::InvalidSection,
+    ErrorCode::SectionRedefinition,
+    ErrorCode::SectionOverflow,
+    ErrorCode::SectionOverlap,
+    ErrorCode::DataInRamSection
+];
+
+impl ErrorCode {
+
+    /// Looks up a error or warning by its code, ignoring case
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        ERROR_CODES.iter().cloned()
+            .chain(WARNINGS.iter().map(|w| ErrorCode::Warning(*w)))
+            .find(|c| c.as_str().eq_ignore_ascii_case(code))
+    }
+
+    /// Returns the stable code which is shown to the user
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ErrorCode::UnexpectedCharacter => "E0101",
+            ErrorCode::InvalidEscapeSequence => "E0102",
+            ErrorCode::UnclosedString => "E0103",
+            ErrorCode::InvalidStringContents => "E0104",
+            ErrorCode::InvalidOperator => "E0105",
+            ErrorCode::NumberTooLong => "E0106",
+            ErrorCode::EmptyName => "E0107",
+            ErrorCode::UnclosedInterpolation => "E0108",
+            ErrorCode::InvalidOffset => "E0109",
+            ErrorCode::ExpectedOffsetNumber => "E0201",
+            ErrorCode::UnexpectedMacroArg => "E0202",
+            ErrorCode::NestedMacroArgs => "E0203",
+            ErrorCode::ExpectedMacroName => "E0204",
+            ErrorCode::UnexpectedMacroEnd => "E0205",
+            ErrorCode::UnexpectedConditionalElse => "E0206",
+            ErrorCode::IncompleteConditional => "E0207",
+            ErrorCode::InvalidCall => "E0208",
+            ErrorCode::InvalidUnaryOperator => "E0209",
+            ErrorCode::ExpectedValue => "E0210",
+            ErrorCode::UnbalancedParenthesis => "E0211",
+            ErrorCode::MissingOperand => "E0212",
+            ErrorCode::UnexpectedToken => "E0213",
+            ErrorCode::LocalLabelWithoutScope => "E0214",
+            ErrorCode::UnexpectedNoWarnEnd => "E0215",
+            ErrorCode::UnknownWarning => "E0216",
+            ErrorCode::UnclosedMacro => "E0217",
+            ErrorCode::InvalidInstruction => "E0218",
+            ErrorCode::UnknownMacro => "E0219",
+            ErrorCode::MacroArguments => "E0220",
+            ErrorCode::ExpansionDepth => "E0221",
+            ErrorCode::UnclosedRepeat => "E0222",
+            ErrorCode::UnexpectedRepeatEnd => "E0223",
+            ErrorCode::UndefinedName => "E0301",
+            ErrorCode::UnknownFunction => "E0302",
+            ErrorCode::InvalidOperands => "E0303",
+            ErrorCode::DivisionByZero => "E0304",
+            ErrorCode::InvalidFormat => "E0305",
+            ErrorCode::UnresolvedLabel => "E0306",
+            ErrorCode::MacroAsValue => "E0307",
+            ErrorCode::OperandOutOfRange => "E0308",
+            ErrorCode::BuiltinRedefinition => "E0401",
+            ErrorCode::ConstantRedefinition => "E0402",
+            ErrorCode::VariableRedefinition => "E0403",
+            ErrorCode::ExpectedString => "E0404",
+            ErrorCode::InvalidSymbolName => "E0405",
+            ErrorCode::LabelRedefinition => "E0406",
+            ErrorCode::MacroRedefinition => "E0407",
+            ErrorCode::FileNotFound => "E0501",
+            ErrorCode::InvalidObjectFile => "E0502",
+            ErrorCode::IncludeDepth => "E0503",
+            ErrorCode::CodeOutsideSection => "E0601",
+            ErrorCode::InvalidSection => "E0602",
+            ErrorCode::SectionRedefinition => "E0603",
+            ErrorCode::SectionOverflow => "E0604",
+            ErrorCode::SectionOverlap => "E0605",
+            ErrorCode::DataInRamSection => "E0606",
+            ErrorCode::Warning(Warning::Truncation) => "W0001",
+            ErrorCode::Warning(Warning::Shadow) => "W0002",
+            ErrorCode::Warning(Warning::Unused) => "W0003",
+            ErrorCode::Warning(Warning::Deprecated) => "W0004",
+            ErrorCode::Warning(Warning::Unreachable) => "W0005",
+            ErrorCode::Warning(Warning::Overwrite) => "W0006"
+        }
+    }
+
+}
+
+/// A macro invocation or REPT iteration whose body produced code or a
+/// diagnostic
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expansion {
+    pub name: String,
+
+    /// The location of the invocation
+    pub span: Span,
+
+    /// The iteration of a REPT block starting at 0, none for macros
+    pub iteration: Option<usize>
+}
+
+/// Additional information shown below the locations of a diagnostic
+#[derive(Debug, PartialEq, Clone)]
+pub enum Note {
+    Text(String),
+
+    /// A macro expansion the primary location is part of
+    Expansion(Expansion)
+}
+
+/// A error or warning with its location and additional information
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+
+    /// The primary location the diagnostic refers to
+    pub span: Span,
+
+    /// Additional locations with a short description each
+    pub labels: Vec<(Span, String)>,
+
+    pub notes: Vec<Note>
+}
+
+impl Diagnostic {
+
+    pub fn error(code: ErrorCode, message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message,
+            span,
+            labels: vec![],
+            notes: vec![]
+        }
+    }
+
+    pub fn warning(warning: Warning, message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: ErrorCode::Warning(warning),
+            message,
+            span,
+            labels: vec![],
+            notes: vec![]
+        }
+    }
+
+    /// Returns a copy of the diagnostic which points to another location
+    pub fn with_span(mut self, span: Span) -> Diagnostic {
+        self.span = span;
+        self
+    }
+
+    /// Adds a secondary location to the diagnostic
+    pub fn with_label(mut self, span: Span, label: &str) -> Diagnostic {
+        self.labels.push((span, label.to_string()));
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Diagnostic {
+        self.notes.push(Note::Text(note));
+        self
+    }
+
+    /// Replaces the macro expansions the diagnostic was reported in, starting
+    /// with the innermost one
+    pub fn with_expansions(mut self, expansions: Vec<Expansion>) -> Diagnostic {
+        self.notes.retain(|n| !matches!(n, Note::Expansion(_)));
+        self.notes.extend(expansions.into_iter().map(Note::Expansion));
+        self
+    }
+
+    /// Returns the macro expansions the primary location is part of,
+    /// starting with the innermost one
+    pub fn expansions(&self) -> Vec<&Expansion> {
+        self.notes.iter().filter_map(|n| match *n {
+            Note::Expansion(ref expansion) => Some(expansion),
+            Note::Text(_) => None
+
+        }).collect()
+    }
+
+    /// Returns the textual notes of the diagnostic
+    pub fn text_notes(&self) -> Vec<&str> {
+        self.notes.iter().filter_map(|n| match *n {
+            Note::Text(ref text) => Some(&text[..]),
+            Note::Expansion(_) => None
+
+        }).collect()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+        write!(f, "{}[{}]: {}", severity, self.code.as_str(), self.message)
+    }
+}
+
+/// A region of a source file in which some warnings are not reported
+#[derive(Debug)]
+struct Suppression {
+    warnings: Vec<Warning>,
+    file: usize,
+    start: usize,
+    end: Option<usize>
+}
+
+/// Collects the diagnostics reported during a compilation, once the error
+/// limit is reached all further diagnostics are dropped
+#[derive(Debug)]
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+    errors: usize,
+
+    /// The maximum number of errors to report, 0 for no limit
+    error_limit: usize,
+
+    warnings: Vec<Warning>,
+    warnings_as_errors: bool,
+    suppressions: Vec<Suppression>,
+
+    /// The macro expansions which are currently parsed, starting with the
+    /// outermost one
+    expansions: Vec<Expansion>
+}
+
+impl Diagnostics {
+
+    pub fn new(error_limit: usize) -> Diagnostics {
+        Diagnostics {
+            list: vec![],
+            errors: 0,
+            error_limit,
+            warnings: WARNINGS.iter().filter(|w| w.is_default()).cloned().collect(),
+            warnings_as_errors: false,
+            suppressions: vec![],
+            expansions: vec![]
+        }
+    }
+
+    /// Reports a diagnostic, diagnostics without a expansion trace receive
+    /// the one of the macro expansions which are currently parsed
+    pub fn report(&mut self, mut diagnostic: Diagnostic) {
+
+        if self.limit_reached() {
+            return;
+        }
+
+        if diagnostic.expansions().is_empty() {
+            diagnostic = diagnostic.with_expansions(self.expansion_trace());
+        }
+
+        if let ErrorCode::Warning(warning) = diagnostic.code {
+
+            // Warnings within macros are also suppressed around the invocation
+            let suppressed = self.is_suppressed(warning, diagnostic.span) || diagnostic.expansions().iter().any(|e| {
+                self.is_suppressed(warning, e.span)
+            });
+
+            if !self.warnings.contains(&warning) || suppressed {
+                return;
+
+            } else if self.warnings_as_errors {
+                diagnostic.severity = Severity::Error;
+                diagnostic = diagnostic.with_note("warnings are treated as errors because of -Werror".to_string());
+            }
+
+        }
+
+        if diagnostic.is_error() {
+            self.errors += 1;
+            if self.limit_reached() {
+                diagnostic = diagnostic.with_note(format!(
+                    "stopping after {} errors, use --max-errors to change the limit",
+                    self.errors
+                ));
+            }
+        }
+
+        self.list.push(diagnostic);
+
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors
+    }
+
+    /// Returns whether no further errors should be reported
+    pub fn limit_reached(&self) -> bool {
+        self.error_limit > 0 && self.errors >= self.error_limit
+    }
+
+    pub fn set_error_limit(&mut self, limit: usize) {
+        self.error_limit = limit;
+    }
+
+    pub fn set_warning(&mut self, warning: Warning, enabled: bool) {
+        self.warnings.retain(|w| *w != warning);
+        if enabled {
+            self.warnings.push(warning);
+        }
+    }
+
+    pub fn set_warnings_as_errors(&mut self, enabled: bool) {
+        self.warnings_as_errors = enabled;
+    }
+
+    /// Starts a region in which the given warnings are not reported, the
+    /// region ends with the next call to end_suppression for the same file
+    pub fn start_suppression(&mut self, warnings: Vec<Warning>, span: Span) {
+        self.suppressions.push(Suppression {
+            warnings,
+            file: span.file,
+            start: span.start,
+            end: None
+        });
+    }
+
+    /// Ends the innermost open suppression region of the file, returns false
+    /// if there is none
+    pub fn end_suppression(&mut self, span: Span) -> bool {
+        match self.suppressions.iter_mut().rev().find(|s| s.file == span.file && s.end.is_none()) {
+            Some(suppression) => {
+                suppression.end = Some(span.end);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Starts parsing the body of a macro invocation or REPT iteration
+    pub fn enter_expansion(&mut self, expansion: Expansion) {
+        self.expansions.push(expansion);
+    }
+
+    pub fn leave_expansion(&mut self) {
+        self.expansions.pop();
+    }
+
+    /// Returns the macro expansions which are currently parsed, starting
+    /// with the innermost one
+    pub fn expansion_trace(&self) -> Vec<Expansion> {
+        self.expansions.iter().rev().cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.errors = 0;
+        self.suppressions.clear();
+        self.expansions.clear();
+    }
+
+    pub fn as_slice(&self) -> &[Diagnostic] {
+        &self.list
+    }
+
+    fn is_suppressed(&self, warning: Warning, span: Span) -> bool {
+        self.suppressions.iter().any(|s| {
+            s.file == span.file && span.start >= s.start
+                && s.end.is_none_or(|end| span.start < end)
+                && s.warnings.contains(&warning)
+        })
+    }
+
+}
@@ -0,0 +1,163 @@
+use compiler::{Argument, EntryKind, Instruction, Operand, Section, SourceFile, SymbolKind, SymbolTable, label_name};
+use compiler::json::Json;
+use parser::{Expression, Span};
+
+/// The version of the dump format, increased with every incompatible change
+pub const DUMP_VERSION: u32 = 1;
+
+/// Renders all linked sections, labels, constants, variables and
+/// instructions as a single JSON object
+///
+/// ```text
+/// {
+///   "version": 1,
+///   "sections": [{ "name", "region", "bank", "address", "size", "bytes": [u8] }],
+///   "labels": [{ "name", "section", "bank", "address", "file", "line" }],
+///   "constants": [{ "name", "kind", "value": number | string | null, "file", "line" }],
+///   "instructions": [{
+///     "section", "bank", "address", "mnemonic", "operands": [string],
+///     "bytes": [u8], "cycles", "cycles_not_taken", "file", "line", "column"
+///   }]
+/// }
+/// ```
+///
+/// Bytes are only listed for ROM sections, "cycles_not_taken" is null for
+/// instructions without a condition and locations are null for constants
+/// which were defined on the command line. Values which are infinite or not
+/// a number are null. The "kind" of a constant is either "constant" or
+/// "variable", variables are listed with their final value.
+pub fn render_dump(sections: &[Section], symbols: &SymbolTable, files: &[SourceFile]) -> String {
+
+    let mut labels = vec![];
+    let mut instructions = vec![];
+
+    for section in sections {
+        for entry in &section.entries {
+
+            let address = section.address + entry.offset;
+            let mut entries = vec![
+                ("section", Json::string(&section.name)),
+                ("bank", Json::number(section.bank as u32)),
+                ("address", Json::number(address as u32))
+            ];
+
+            match entry.kind {
+                EntryKind::Label(ref name) => {
+                    entries.insert(0, ("name", Json::string(label_name(name))));
+                    entries.extend(location(entry.span, files, false));
+                    labels.push(Json::object(entries));
+                },
+                EntryKind::Instruction(ref instruction) => {
+                    let (cycles, not_taken) = instruction.cycles();
+                    let bytes = section.data.get(entry.offset..entry.offset + entry.size()).unwrap_or(&[]);
+                    entries.push(("mnemonic", Json::string(&instruction.mnemonic)));
+                    entries.push(("operands", Json::Array(format_operands(instruction, address, symbols))));
+                    entries.push(("bytes", format_bytes(bytes)));
+                    entries.push(("cycles", Json::number(cycles as u32)));
+                    entries.push(("cycles_not_taken", if cycles == not_taken {
+                        Json::Null
+
+                    } else {
+                        Json::number(not_taken as u32)
+                    }));
+                    entries.extend(location(entry.span, files, true));
+                    instructions.push(Json::object(entries));
+                },
+                _ => {}
+            }
+
+        }
+    }
+
+    let mut constants: Vec<_> = symbols.symbols().into_iter().filter(|&(_, s)| {
+        s.kind == SymbolKind::Constant || s.kind == SymbolKind::Variable
+
+    }).collect();
+    constants.sort_by_key(|&(name, _)| name);
+
+    let constants = constants.into_iter().filter_map(|(name, symbol)| {
+        let value = match symbol.value {
+            Expression::Number(value, _) => Json::number(value),
+            Expression::String(ref value, _) => Json::string(value),
+            _ => return None
+        };
+        let mut entries = vec![("name", Json::string(name)), ("kind", Json::string(symbol.kind.name())), ("value", value)];
+        entries.extend(location(symbol.span, files, false));
+        Some(Json::object(entries))
+
+    }).collect();
+
+    Json::object(vec![
+        ("version", Json::number(DUMP_VERSION)),
+        ("sections", Json::Array(sections.iter().map(|section| Json::object(vec![
+            ("name", Json::string(&section.name)),
+            ("region", Json::string(section.region.name())),
+            ("bank", Json::number(section.bank as u32)),
+            ("address", Json::number(section.address as u32)),
+            ("size", Json::number(section.size as u32)),
+            ("bytes", format_bytes(&section.data))
+
+        ])).collect())),
+        ("labels", Json::Array(labels)),
+        ("constants", Json::Array(constants)),
+        ("instructions", Json::Array(instructions))
+
+    ]).to_string()
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn location(span: Span, files: &[SourceFile], column: bool) -> Vec<(&'static str, Json)> {
+
+    let (file, line, col) = match files.get(span.file) {
+        Some(file) if span != Span::default() => (
+            Json::string(&file.display_path()),
+            Json::number(span.line as u32),
+            Json::number(span.column as u32)
+        ),
+        _ => (Json::Null, Json::Null, Json::Null)
+    };
+
+    if column {
+        vec![("file", file), ("line", line), ("column", col)]
+
+    } else {
+        vec![("file", file), ("line", line)]
+    }
+
+}
+
+fn format_bytes(bytes: &[u8]) -> Json {
+    Json::Array(bytes.iter().map(|b| Json::number(*b)).collect())
+}
+
+/// Formats the operands with all names replaced by their linked values
+fn format_operands(instruction: &Instruction, address: usize, symbols: &SymbolTable) -> Vec<Json> {
+
+    // Addresses and words are always shown with four digits
+    let digits = match instruction.argument {
+        Some(Argument::Byte(_)) | Some(Argument::SignedByte(_)) |
+        Some(Argument::Vector(_)) | Some(Argument::BitIndex(_)) => 2,
+        _ => 4
+    };
+
+    let value = |expr: &Expression| match expr.evaluate(&mut |n| symbols.get(n).map(|s| s.value.clone())) {
+        Expression::Number(value, _) if value < 0.0 => format!("{}", value),
+        Expression::Number(value, _) => format!("${:0>digits$X}", value as i32, digits = digits),
+        _ => "?".to_string()
+    };
+
+    instruction.operands.iter().map(|operand| Json::String(match *operand {
+        Operand::Register(ref name) => name.clone(),
+        Operand::Memory(ref name) if name == "hli" => "[hl+]".to_string(),
+        Operand::Memory(ref name) if name == "hld" => "[hl-]".to_string(),
+        Operand::Memory(ref name) => format!("[{}]", name),
+        Operand::Address(ref expr) => format!("[{}]", value(expr)),
+        Operand::Value(ref expr) => value(expr),
+        Operand::StackOffset(ref expr) => format!("sp+{}", value(expr)),
+        Operand::Offset(offset) => format!("${:0>4X}", address as i32 + offset)
+
+    })).collect()
+
+}
@@ -0,0 +1,827 @@
+use compiler::{ErrorCode, Warning};
+
+/// Returns a longer description of a error code together with a example of
+/// the wrong and the corrected code
+pub fn explain(code: ErrorCode) -> &'static str {
+    match code {
+
+        // Lexing
+        ErrorCode::UnexpectedCharacter => r#"The source contains a character which is not part of the assembler syntax.
+
+Erroneous code example:
+
+    ld a, 5 # load the counter
+
+Comments start with a semicolon:
+
+    ld a, 5 ; load the counter"#,
+
+        ErrorCode::InvalidEscapeSequence => r#"A string literal contains a backslash which is not followed by one of the
+supported escape characters: \0 \b \t \n \v \r \" \' or \\.
+
+Erroneous code example:
+
+    DB "C:\games"
+
+Escape the backslash itself:
+
+    DB "C:\\games""#,
+
+        ErrorCode::UnclosedString => r#"A string literal is missing its closing quote. Strings cannot span multiple
+lines.
+
+Erroneous code example:
+
+    TITLE EQUS "Tetris
+
+Close the string with the same quote it was opened with:
+
+    TITLE EQUS "Tetris""#,
+
+        ErrorCode::InvalidStringContents => r#"A string literal contains bytes which are not valid UTF-8. This usually
+happens when a source file was saved in a legacy encoding.
+
+Erroneous code example (saved as Latin-1):
+
+    DB "Größe"
+
+Save the source file as UTF-8 or use numeric values instead:
+
+    DB "Gr", $C3, $B6, $C3, $9F, "e""#,
+
+        ErrorCode::InvalidOperator => r#"A operator character was found which does not form any supported operator.
+
+Erroneous code example:
+
+    DB 1 <> 2
+
+Use one of the supported comparison operators:
+
+    DB 1 != 2"#,
+
+        ErrorCode::NumberTooLong => r#"A number literal has more digits than the assembler supports. Decimal and
+binary literals are limited to 8 digits, hex literals to 4 digits.
+
+Erroneous code example:
+
+    DW $0FFFF
+
+Remove the leading zeros:
+
+    DW $FFFF"#,
+
+        ErrorCode::EmptyName => r#"A name consists only of a interpolation which does not contain any
+characters.
+
+Erroneous code example:
+
+    {}: nop
+
+Put the name of a string constant into the braces:
+
+    PREFIX EQUS "Game"
+    {PREFIX}: nop"#,
+
+        ErrorCode::UnclosedInterpolation => r#"A interpolation within a name or string is missing its closing brace.
+
+Erroneous code example:
+
+    PREFIX EQUS "Game"
+    {PREFIX_Start: nop
+
+Close the interpolation:
+
+    {PREFIX}_Start: nop"#,
+
+        ErrorCode::InvalidOffset => r#"A relative offset must start with "@" followed by the direction "+" or "-".
+
+Erroneous code example:
+
+    jr @2
+
+Specify the direction of the offset:
+
+    jr @+2"#,
+
+        // Parsing
+        ErrorCode::ExpectedOffsetNumber => r#"A relative offset is missing the number of bytes to skip.
+
+Erroneous code example:
+
+    jr @-
+
+Add the offset in bytes:
+
+    jr @-2"#,
+
+        ErrorCode::UnexpectedMacroArg => r#"Macro arguments like "@value" can only be used within the signature and the
+body of a macro.
+
+Erroneous code example:
+
+    ld a, @value
+
+Define a macro which takes the argument:
+
+    MACRO load(@value)
+        ld a, @value
+    ENDMACRO"#,
+
+        ErrorCode::NestedMacroArgs => r#"A MACRO directive appeared within the argument list of another macro.
+
+Erroneous code example:
+
+    MACRO outer(@a, MACRO inner(@b))
+
+Define each macro on its own:
+
+    MACRO outer(@a)
+    ENDMACRO"#,
+
+        ErrorCode::ExpectedMacroName => r#"A MACRO directive must be followed by the name of the macro.
+
+Erroneous code example:
+
+    MACRO (@value)
+
+Name the macro:
+
+    MACRO load(@value)"#,
+
+        ErrorCode::UnexpectedMacroEnd => r#"A ENDMACRO directive appeared outside of a macro definition.
+
+Erroneous code example:
+
+    nop
+    ENDMACRO
+
+Remove the directive or add the missing MACRO:
+
+    MACRO pause()
+        nop
+    ENDMACRO"#,
+
+        ErrorCode::UnexpectedConditionalElse => r#"A ":" was found in a expression without a preceding "?".
+
+Erroneous code example:
+
+    DB DEBUG : 1
+
+Conditional expressions have the form "condition ? a : b":
+
+    DB DEBUG ? 0 : 1"#,
+
+        ErrorCode::IncompleteConditional => r#"A conditional expression is missing one of its parts.
+
+Erroneous code example:
+
+    DB DEBUG ? 1
+
+Add the value for the false case:
+
+    DB DEBUG ? 1 : 0"#,
+
+        ErrorCode::InvalidCall => r#"Only names of built-in functions can be called.
+
+Erroneous code example:
+
+    NAME EQUS ("%d")(3)
+
+Call the function by its name:
+
+    NAME EQUS STRFMT("%d", 3)"#,
+
+        ErrorCode::InvalidUnaryOperator => r#"A operator was used in a position where it does not make sense. Only "-",
+"+", "!" and "~" can appear in front of a value, and these cannot appear
+between two values (with the exception of "-" and "+").
+
+Erroneous code example:
+
+    DB *5
+    DB 5 ~ 3
+
+Use the operator with the right number of values:
+
+    DB 2 * 5
+    DB 5 ^ ~3"#,
+
+        ErrorCode::ExpectedValue => r#"A directive or a command line definition is missing its value, or the value
+is not a valid expression.
+
+Erroneous code example:
+
+    SPEED EQU
+
+Provide a value:
+
+    SPEED EQU 4"#,
+
+        ErrorCode::UnbalancedParenthesis => r#"The parenthesis of a expression do not match up.
+
+Erroneous code example:
+
+    DB (1 + 2
+
+Close every opened parenthesis:
+
+    DB (1 + 2)"#,
+
+        ErrorCode::MissingOperand => r#"A operator or function argument is missing one of its values.
+
+Erroneous code example:
+
+    DB 1 +
+    NAME EQUS STRFMT("%d", )
+
+Add the missing value:
+
+    DB 1 + 2
+    NAME EQUS STRFMT("%d", 2)"#,
+
+        ErrorCode::UnexpectedToken => r#"A token was found in a position where it cannot be used.
+
+Erroneous code example:
+
+    DB 1 2
+
+Separate the values with a comma or combine them with a operator:
+
+    DB 1, 2"#,
+
+        ErrorCode::LocalLabelWithoutScope => r#"Local labels belong to the global label preceding them, so they cannot be
+defined before the first global label of a file.
+
+Erroneous code example:
+
+    .loop:
+        jr .loop
+
+Add a global label first:
+
+    Main:
+    .loop:
+        jr .loop"#,
+
+        ErrorCode::UnexpectedNoWarnEnd => r#"A ENDNOWARN directive was found without a preceding NOWARN directive in the
+same file.
+
+Erroneous code example:
+
+    DB 300
+    ENDNOWARN
+
+Start the region with NOWARN:
+
+    NOWARN truncation
+    DB 300
+    ENDNOWARN"#,
+
+        ErrorCode::UnknownWarning => r#"A NOWARN directive or a -W option refers to a warning which does not exist.
+The available warnings are: truncation, shadow, unused, deprecated,
+unreachable and overwrite.
+
+Erroneous code example:
+
+    NOWARN overflow
+
+Use the name of a existing warning:
+
+    NOWARN truncation"#,
+
+        ErrorCode::UnclosedMacro => r#"A macro definition is missing its ENDMACRO directive, so the rest of the file
+would become part of the macro.
+
+Erroneous code example:
+
+    MACRO pause()
+        nop
+
+    Main:
+
+End the macro body:
+
+    MACRO pause()
+        nop
+    ENDMACRO
+
+    Main:"#,
+
+        ErrorCode::InvalidInstruction => r#"The operands are not supported by the instruction. The SM83 CPU only
+supports a fixed set of operand combinations, most notably:
+
+  - memory can only be loaded into or stored from "a", except for "[hl]"
+    which works with all 8 bit registers
+  - "[bc]" and "[de]" can only be used together with "a"
+  - 16 bit registers cannot be copied into each other, except "ld sp, hl"
+  - arithmetic and logic operations always target "a", 16 bit additions
+    always target "hl" (or "sp" with a signed 8 bit value)
+  - "jr" only supports the conditions nz, z, nc and c
+
+Erroneous code example:
+
+    ld b, [de]
+    ld bc, hl
+
+Load through the accumulator and copy 16 bit registers by halves:
+
+    ld a, [de]
+    ld b, a
+    ld b, h
+    ld c, l"#,
+
+        ErrorCode::UnknownMacro => r#"A line starts with a name which is neither a instruction, a directive nor a
+macro which was defined before. Instructions are written in lowercase and
+directives in uppercase.
+
+Erroneous code example:
+
+    LD a, 1
+    db 1, 2, 3
+    pause
+
+Fix the spelling and define macros before invoking them:
+
+    MACRO pause()
+        nop
+    ENDMACRO
+
+    ld a, 1
+    DB 1, 2, 3
+    pause"#,
+
+        ErrorCode::MacroArguments => r#"A macro was invoked with a different number of arguments than it has
+parameters, or one of the arguments is not a single value. The arguments
+follow the name of the macro, separated by commas.
+
+Erroneous code example:
+
+    MACRO load(@register, @value)
+        ld @register, @value
+    ENDMACRO
+
+    load a
+
+Pass a value for every parameter:
+
+    load a, 1"#,
+
+        ErrorCode::ExpansionDepth => r#"Macros can only be expanded up to 64 levels deep, which usually means that
+a macro invokes itself.
+
+Erroneous code example:
+
+    MACRO forever()
+        nop
+        forever
+    ENDMACRO
+
+Remove the invocation from the body of the macro, code which should be
+repeated can be placed into a REPT block instead:
+
+    REPT 16
+        nop
+    ENDR"#,
+
+        ErrorCode::UnclosedRepeat => r#"A REPT block is missing its ENDR directive, so the rest of the file would
+be repeated.
+
+Erroneous code example:
+
+    REPT 4
+        nop
+
+    Main:
+
+End the block:
+
+    REPT 4
+        nop
+    ENDR
+
+    Main:"#,
+
+        ErrorCode::UnexpectedRepeatEnd => r#"A ENDR directive appeared outside of a REPT block.
+
+Erroneous code example:
+
+    nop
+    ENDR
+
+Remove the directive or add the missing REPT:
+
+    REPT 2
+        nop
+    ENDR"#,
+
+        // Evaluation
+        ErrorCode::UndefinedName => r#"A expression refers to a name which has not been defined. Constants and
+variables must be defined before they are used in a expression, only names
+which are defined by object files or archives can be referred to before.
+
+Erroneous code example:
+
+    DISTANCE EQU SPEED * 8
+    SPEED EQU 4
+
+Define the constant before using it:
+
+    SPEED EQU 4
+    DISTANCE EQU SPEED * 8"#,
+
+        ErrorCode::UnknownFunction => r#"A expression calls a function which is not built into the assembler.
+
+Erroneous code example:
+
+    NAME EQUS FORMAT("%d", 2)
+
+Use one of the built-in functions:
+
+    NAME EQUS STRFMT("%d", 2)"#,
+
+        ErrorCode::InvalidOperands => r#"A operator or function was used with values it does not support, for example
+a string in a arithmetic operation.
+
+Erroneous code example:
+
+    DB "A" * 2
+
+Use values of the right type:
+
+    DB 65 * 2"#,
+
+        ErrorCode::DivisionByZero => r#"A expression divides by zero or uses zero as the right side of a modulo
+operation.
+
+Erroneous code example:
+
+    TILES EQU 0
+    DB 256 / TILES
+
+Make sure the divisor is not zero:
+
+    TILES EQU 16
+    DB 256 / TILES"#,
+
+        ErrorCode::InvalidFormat => r#"A format string passed to STRFMT or used in a interpolation is invalid, or
+the number of arguments does not match its format specifiers.
+
+Erroneous code example:
+
+    NAME EQUS STRFMT("%s-%d", "Level")
+
+Pass a argument for every format specifier:
+
+    NAME EQUS STRFMT("%s-%d", "Level", 1)"#,
+
+        ErrorCode::UnresolvedLabel => r#"The address of a label is only known after all sections were placed, so
+labels cannot be used where a value is needed while parsing, like the value
+of a constant.
+
+Erroneous code example:
+
+    Start:
+    START_ADDRESS EQU Start
+
+Reference the label directly where its address is needed:
+
+    Start:
+        jp Start"#,
+
+        ErrorCode::MacroAsValue => r#"A macro was used within a expression, but macros can only be invoked as a
+statement.
+
+Erroneous code example:
+
+    MACRO pause()
+        nop
+    ENDMACRO
+    DB pause
+
+Invoke the macro on its own line:
+
+    pause"#,
+
+        ErrorCode::OperandOutOfRange => r#"The value of a instruction operand does not fit into its encoding. 8 bit
+values must be between -128 and 255, 16 bit values between -32768 and 65535,
+"ldh" addresses between $FF00 and $FFFF, "jr" targets at most 128 bytes
+away, "rst" vectors one of $00, $08, ..., $38 and bit indices between 0
+and 7.
+
+Erroneous code example:
+
+    ld a, 256
+    bit 8, a
+
+Use values within the range of the operand:
+
+    ld a, 255
+    bit 7, a"#,
+
+        // Symbols
+        ErrorCode::BuiltinRedefinition => r#"The names __FILE__, __LINE__, __SECTION__, __ADDRESS__, __VERSION__,
+__DATE__ and __TIME__ are provided by the assembler and cannot be defined.
+
+Erroneous code example:
+
+    __VERSION__ EQUS "1.0"
+
+Use a name of your own:
+
+    GAME_VERSION EQUS "1.0""#,
+
+        ErrorCode::ConstantRedefinition => r#"A constant defined via EQU, EQUS or -D can only be defined once.
+
+Erroneous code example:
+
+    LIVES EQU 3
+    LIVES EQU 5
+
+Use SET for values which change, or remove one of the definitions:
+
+    LIVES SET 3
+    LIVES SET 5"#,
+
+        ErrorCode::VariableRedefinition => r#"A variable defined via SET or "=" can only be redefined as a variable.
+
+Erroneous code example:
+
+    COUNT SET 0
+    COUNT EQU 1
+
+Keep using SET:
+
+    COUNT SET 0
+    COUNT SET 1"#,
+
+        ErrorCode::ExpectedString => r#"A EQUS constant must have a string value, and the INCLUDE and INCBIN
+directives expect the path of a file as a string.
+
+Erroneous code example:
+
+    TITLE EQUS 42
+    INCLUDE constants.inc
+
+Use EQU for numbers or quote the value:
+
+    TITLE EQUS "42"
+    INCLUDE "constants.inc""#,
+
+        ErrorCode::InvalidSymbolName => r#"A symbol defined on the command line via -D must be a plain name which could
+also be defined in a source file.
+
+Erroneous code example:
+
+    gbasm -D 2PLAYER main.gb.s
+
+Use a name which starts with a letter or underscore:
+
+    gbasm -D TWO_PLAYER main.gb.s"#,
+
+        ErrorCode::LabelRedefinition => r#"A label can only be defined once. Local labels can be reused under different
+global labels.
+
+Erroneous code example:
+
+    Main:
+        nop
+    Main:
+        nop
+
+Give each label a unique name:
+
+    Main:
+        nop
+    MainLoop:
+        nop"#,
+
+        ErrorCode::MacroRedefinition => r#"A macro can only be defined once, and its name cannot be used for other
+symbols.
+
+Erroneous code example:
+
+    MACRO pause()
+        nop
+    ENDMACRO
+    pause EQU 1
+
+Give each symbol a unique name:
+
+    PAUSE_FRAMES EQU 1"#,
+
+        // Files
+        ErrorCode::FileNotFound => r#"A source file could not be opened. Paths of included files are relative to
+the directory of the first source file.
+
+Erroneous code example:
+
+    gbasm mian.gb.s
+
+Check the spelling and location of the file:
+
+    gbasm main.gb.s"#,
+
+        ErrorCode::InvalidObjectFile => r#"A input file ending in ".o" is not a valid object file, or one ending in
+".a" is not a valid archive. Object files and archives are only compatible
+with the version of gbasm which created them and cannot be edited by hand.
+
+Erroneous code example:
+
+    # main.o was created by a older version of gbasm
+    gbasm -o game.gb main.o
+
+Rebuild outdated or damaged object files from their sources:
+
+    gbasm -c main.gb.s -o main.o
+    gbasm -o game.gb main.o"#,
+
+        ErrorCode::IncludeDepth => r#"Files can only be nested up to 16 INCLUDE directives deep, which usually
+means that a file includes itself.
+
+Erroneous code example:
+
+    ; main.gb.s
+    INCLUDE "main.gb.s"
+
+Include shared files only once from the main file:
+
+    ; main.gb.s
+    INCLUDE "hardware.inc""#,
+
+        // Sections
+        ErrorCode::CodeOutsideSection => r#"Labels, instructions and data must be placed inside a section, which tells
+the linker in which memory region they belong.
+
+Erroneous code example:
+
+    Main:
+        nop
+
+Start a section first:
+
+    SECTION "Main", ROM0
+    Main:
+        nop"#,
+
+        ErrorCode::InvalidSection => r#"A SECTION directive is malformed, uses a unknown region, or requests a
+address or bank which does not exist in its region. The regions are ROM0,
+ROMX, VRAM, SRAM, WRAM0, WRAMX, OAM and HRAM.
+
+Erroneous code example:
+
+    SECTION "Main", ROM0[$4000]
+
+Use a address within the region:
+
+    SECTION "Main", ROMX[$4000], BANK[1]"#,
+
+        ErrorCode::SectionRedefinition => r#"Section names must be unique across all source files.
+
+Erroneous code example:
+
+    SECTION "Code", ROM0
+    SECTION "Code", ROMX
+
+Give each section a unique name:
+
+    SECTION "Code", ROM0
+    SECTION "Code Bank 1", ROMX"#,
+
+        ErrorCode::SectionOverflow => r#"A section does not fit into its region or there is no free space left in
+any of the banks it can be placed in.
+
+Erroneous code example:
+
+    SECTION "Buffer", HRAM
+        DS 256
+
+Place the section into a larger region or split it up:
+
+    SECTION "Buffer", WRAM0
+        DS 256"#,
+
+        ErrorCode::SectionOverlap => r#"Two sections were placed at fixed addresses which overlap each other.
+
+Erroneous code example:
+
+    SECTION "A", ROM0[$0100]
+        DS 16
+    SECTION "B", ROM0[$0108]
+        DS 16
+
+Move one of the sections or let the linker place it:
+
+    SECTION "B", ROM0
+        DS 16"#,
+
+        ErrorCode::DataInRamSection => r#"RAM regions are not part of the ROM image, so their sections can only
+reserve space via DS but not contain instructions or data.
+
+Erroneous code example:
+
+    SECTION "Variables", WRAM0
+    wLives:
+        DB 3
+
+Reserve the space and initialize it from code:
+
+    SECTION "Variables", WRAM0
+    wLives:
+        DS 1"#,
+
+        // Warnings
+        ErrorCode::Warning(Warning::Truncation) => r#"A value does not fit into the storage of a DB (-128 to 255) or DW (-32768 to
+65535) directive and only its lower bits are stored. Disable with
+-Wno-truncation.
+
+Erroneous code example:
+
+    DB 300
+
+Use a larger storage or reduce the value:
+
+    DW 300"#,
+
+        ErrorCode::Warning(Warning::Shadow) => r#"A local label was defined twice within the same global label, references
+after the second definition refer to the new one. Disable with -Wno-shadow.
+
+Erroneous code example:
+
+    Main:
+    .loop:
+        jr .loop
+    .loop:
+        jr .loop
+
+Give each local label a unique name:
+
+    Main:
+    .wait:
+        jr .wait
+    .loop:
+        jr .loop"#,
+
+        ErrorCode::Warning(Warning::Unused) => r#"A constant, label or macro is never referenced. Symbols which are used from
+outside of the sources can be listed in a EXPORT directive. This warning is
+disabled by default, enable it with -Wunused.
+
+Erroneous code example:
+
+    Main:
+        call Init
+    Init:
+        ret
+    Unused:
+        ret
+
+Remove the symbol or export it:
+
+    EXPORT Unused"#,
+
+        ErrorCode::Warning(Warning::Deprecated) => r#"Syntax which is only supported for compatibility with other assemblers was
+used. Disable with -Wno-deprecated.
+
+Erroneous code example:
+
+    ldhl sp, 4
+
+Use the standard syntax:
+
+    ld hl, sp + 4"#,
+
+        ErrorCode::Warning(Warning::Unreachable) => r#"A instruction directly follows a unconditional jump or return without a
+label in between, so it can never be executed. Disable with
+-Wno-unreachable.
+
+Erroneous code example:
+
+    Main:
+        jp Start
+        ld a, 1
+
+Add a label which can be jumped to or remove the instruction:
+
+    Main:
+        jp Start
+    .skip:
+        ld a, 1"#,
+
+        ErrorCode::Warning(Warning::Overwrite) => r#"A section with a fixed address replaces bytes of the base ROM given with
+--base which are in use. Only bytes of $00 and $FF are considered padding,
+sections without a fixed address are only placed into padding. Disable with
+-Wno-overwrite.
+
+Erroneous code example:
+
+    SECTION "Patch", ROM0[$0000]
+        jp Start
+
+Place the section into unused space of the base ROM, or disable the warning
+around sections which are meant to patch existing code:
+
+    NOWARN overwrite
+    SECTION "Patch", ROM0[$0000]
+        jp Start
+    ENDNOWARN"#
+
+    }
+}
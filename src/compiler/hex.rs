@@ -0,0 +1,89 @@
+/// The number of data bytes per record
+const RECORD_SIZE: usize = 16;
+
+/// Record types
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+
+/// Renders a ROM image as Intel HEX records, images larger than 64KB are
+/// addressed through extended linear address records
+pub fn render_hex(rom: &[u8]) -> String {
+
+    let mut records = Vec::new();
+    for (index, chunk) in rom.chunks(RECORD_SIZE).enumerate() {
+
+        let address = index * RECORD_SIZE;
+
+        // Switch the upper 16 bits of the address at every 64KB boundary
+        if address > 0 && address & 0xFFFF == 0 {
+            let upper = (address >> 16) as u16;
+            records.push(format_record(0, EXTENDED_LINEAR_ADDRESS, &[(upper >> 8) as u8, upper as u8]));
+        }
+
+        records.push(format_record(address as u16, DATA, chunk));
+
+    }
+
+    records.push(format_record(0, END_OF_FILE, &[]));
+    records.push(String::new());
+    records.join("\n")
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn format_record(address: u16, kind: u8, data: &[u8]) -> String {
+
+    let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    bytes.extend_from_slice(data);
+
+    // Two's complement of the sum of all preceding bytes
+    let checksum = bytes.iter().fold(0u8, |c, b| c.wrapping_add(*b)).wrapping_neg();
+    bytes.push(checksum);
+
+    let mut record = ":".to_string();
+    for b in bytes {
+        record.push_str(&format!("{:0>2X}", b));
+    }
+    record
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::render_hex;
+
+    #[test]
+    fn test_records() {
+        let mut rom = vec![0; 16];
+        rom.extend_from_slice(b"address gap");
+        assert_eq!(render_hex(&rom), [
+            ":1000000000000000000000000000000000000000F0",
+            ":0B0010006164647265737320676170A7",
+            ":00000001FF",
+            ""
+
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_extended_linear_address() {
+        let rom: Vec<u8> = (0..0x20010).map(|i| (i >> 16) as u8).collect();
+        let hex = render_hex(&rom);
+        let records: Vec<&str> = hex.lines().collect();
+
+        // One data record per 16 bytes, a address record every 64KB and the end
+        assert_eq!(records.len(), 0x2001 + 2 + 1);
+        assert_eq!(records[0x0FFF], format!(":10FFF000{}01", "00".repeat(16)));
+        assert_eq!(records[0x1000], ":020000040001F9");
+        assert_eq!(records[0x1001], format!(":10000000{}E0", "01".repeat(16)));
+        assert_eq!(records[0x2001], ":020000040002F8");
+        assert_eq!(records[0x2002], format!(":10000000{}D0", "02".repeat(16)));
+        assert_eq!(records[0x2003], ":00000001FF");
+    }
+
+}
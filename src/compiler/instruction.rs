@@ -0,0 +1,541 @@
+use compiler::{Diagnostic, ErrorCode};
+use parser::{Expression, Operator, Span, Token};
+
+/// Names which refer to registers and conditions instead of symbols
+const REGISTERS: [&str; 17] = [
+    "a", "b", "c", "d", "e", "h", "l",
+    "af", "bc", "de", "hl", "sp", "hli", "hld",
+    "nz", "z", "nc"
+];
+
+/// A single operand of a instruction
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    /// A register or condition like "a", "hl" or "nz"
+    Register(String),
+
+    /// A register used as a memory address like "[hl]", "[hl+]" or "[c]"
+    Memory(String),
+
+    /// A memory address like "[$C000]"
+    Address(Expression),
+
+    Value(Expression),
+
+    /// The stack pointer plus a signed offset like "sp + 4"
+    StackOffset(Expression),
+
+    /// A address relative to the start of the instruction like "@+4"
+    Offset(i32)
+}
+
+impl Operand {
+
+    /// Parses the tokens between two commas into a operand
+    pub fn parse(tokens: Vec<(Token, Span)>, span: Span) -> Result<Operand, Diagnostic> {
+        match &tokens[..] {
+            [(Token::Expression(ref expr), _)] => Operand::from_expression(expr),
+            [(Token::Name(ref name), span)] => Operand::from_expression(&Expression::Name(name.clone(), *span)),
+            [(Token::LocalLabelRef(ref name), span)] => Ok(Operand::Value(Expression::Name(name.clone(), *span))),
+            [(Token::Offset(offset), _)] => Ok(Operand::Offset(*offset)),
+            [(Token::LBrace, _), (Token::Expression(ref expr), _), (Token::RBrace, _)] => Operand::from_memory(expr),
+            [(Token::LBrace, _), (Token::Name(ref name), span), (Token::RBrace, _)] => {
+                Operand::from_memory(&Expression::Name(name.clone(), *span))
+            },
+            _ => {
+                let span = tokens.iter().fold(span, |span, &(_, s)| span.merge(s));
+                Err(Diagnostic::error(ErrorCode::InvalidInstruction, "Invalid instruction operand".to_string(), span))
+            }
+        }
+    }
+
+    /// Returns the operand with the function applied to all of its
+    /// expressions
+    pub fn map<F>(self, f: &mut F) -> Operand where F: FnMut(Expression) -> Expression {
+        match self {
+            Operand::Address(expr) => Operand::Address(f(expr)),
+            Operand::Value(expr) => Operand::Value(f(expr)),
+            Operand::StackOffset(expr) => Operand::StackOffset(f(expr)),
+            operand => operand
+        }
+    }
+
+    fn from_expression(expr: &Expression) -> Result<Operand, Diagnostic> {
+        match *expr {
+            Expression::Invalid(ref diagnostic) => Err(diagnostic.clone()),
+            Expression::Name(ref name, _) if REGISTERS.contains(&&name[..]) => Ok(Operand::Register(name.clone())),
+            Expression::Binary(op, ref left, ref right, span) if is_register(left, "sp") => match op {
+                Operator::Plus => Ok(Operand::StackOffset((**right).clone())),
+                Operator::Minus => Ok(Operand::StackOffset(Expression::Unary(Operator::UnaryMinus, right.clone(), span))),
+                _ => Ok(Operand::Value(expr.clone()))
+            },
+            _ => Ok(Operand::Value(expr.clone()))
+        }
+    }
+
+    fn from_memory(expr: &Expression) -> Result<Operand, Diagnostic> {
+        match *expr {
+            Expression::Invalid(ref diagnostic) => Err(diagnostic.clone()),
+            Expression::Name(ref name, _) if matches!(&name[..], "bc" | "de" | "hl" | "hli" | "hld" | "c") => {
+                Ok(Operand::Memory(name.clone()))
+            },
+
+            // "[$FF00 + c]" is a alternative syntax for "[c]"
+            Expression::Binary(Operator::Plus, ref left, ref right, _) if is_register(right, "c") => match **left {
+                Expression::Number(65280.0, _) => Ok(Operand::Memory("c".to_string())),
+                _ => Ok(Operand::Address(expr.clone()))
+            },
+            _ => Ok(Operand::Address(expr.clone()))
+        }
+    }
+
+}
+
+/// A value of a instruction which can only be encoded once all labels are
+/// resolved
+#[derive(Debug, PartialEq, Clone)]
+pub enum Argument {
+    /// A 8 bit value from -128 to 255
+    Byte(Expression),
+
+    /// A signed 8 bit value from -128 to 127
+    SignedByte(Expression),
+
+    /// A 16 bit value from -32768 to 65535
+    Word(Expression),
+
+    /// A address from $FF00 to $FFFF which is stored as its low byte
+    HighByte(Expression),
+
+    /// A jump target which is stored as a signed 8 bit distance
+    Relative(Expression),
+
+    /// A jump target relative to the start of the instruction
+    RelativeOffset(i32),
+    AbsoluteOffset(i32),
+
+    /// The target of a RST instruction which is part of the opcode
+    Vector(Expression),
+
+    /// The bit index of BIT, RES and SET which is part of the opcode
+    BitIndex(Expression)
+}
+
+impl Argument {
+
+    /// Returns the number of bytes the argument adds to the instruction
+    pub fn size(&self) -> usize {
+        match *self {
+            Argument::Word(_) | Argument::AbsoluteOffset(_) => 2,
+            Argument::Vector(_) | Argument::BitIndex(_) => 0,
+            _ => 1
+        }
+    }
+
+}
+
+/// A SM83 instruction whose opcode is known while parsing but whose
+/// arguments may refer to labels
+#[derive(Debug, PartialEq, Clone)]
+pub struct Instruction {
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+    pub opcode: Vec<u8>,
+    pub argument: Option<Argument>
+}
+
+impl Instruction {
+
+    /// Selects the opcode for the mnemonic and its operands
+    pub fn new(mnemonic: &str, operands: Vec<Operand>, span: Span) -> Result<Instruction, Diagnostic> {
+        match encode(mnemonic, &operands) {
+            Some((opcode, argument)) => Ok(Instruction {
+                mnemonic: mnemonic.to_string(),
+                operands,
+                opcode,
+                argument
+            }),
+            None => Err(Diagnostic::error(
+                ErrorCode::InvalidInstruction,
+                format!("Invalid operands for \"{}\" instruction", mnemonic),
+                span
+            ))
+        }
+    }
+
+    /// Returns the number of bytes of the encoded instruction
+    pub fn size(&self) -> usize {
+        self.opcode.len() + self.argument.as_ref().map_or(0, |a| a.size())
+    }
+
+    /// Returns the number of clock cycles the instruction takes, conditional
+    /// instructions return the cycles for a taken and a not taken branch
+    pub fn cycles(&self) -> (usize, usize) {
+        match self.opcode[0] {
+            0x20 | 0x28 | 0x30 | 0x38 => (12, 8),
+            0xC2 | 0xCA | 0xD2 | 0xDA => (16, 12),
+            0xC4 | 0xCC | 0xD4 | 0xDC => (24, 12),
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => (20, 8),
+            0xCB => {
+                let cycles = cb_cycles(self.opcode[1]);
+                (cycles, cycles)
+            },
+            op => {
+                let cycles = base_cycles(op);
+                (cycles, cycles)
+            }
+        }
+    }
+
+    /// Encodes the instruction at the given address, names in the argument
+    /// are looked up via the passed resolver
+    pub fn to_bytes<F>(&self, address: usize, resolve: &mut F) -> Result<Vec<u8>, Diagnostic> where F: FnMut(&str) -> Option<Expression> {
+
+        let mut bytes = self.opcode.clone();
+        let argument = match self.argument {
+            Some(ref argument) => argument,
+            None => return Ok(bytes)
+        };
+
+        let end = (address + self.size()) as i32;
+        match *argument {
+            Argument::Byte(ref expr) => {
+                let value = evaluate(expr, resolve, -128, 255, "a 8 bit value")?;
+                bytes.push(value as u8);
+            },
+            Argument::SignedByte(ref expr) => {
+                let value = evaluate(expr, resolve, -128, 127, "a signed 8 bit value")?;
+                bytes.push(value as u8);
+            },
+            Argument::Word(ref expr) => {
+                let value = evaluate(expr, resolve, -32768, 65535, "a 16 bit value")?;
+                bytes.push(value as u8);
+                bytes.push((value >> 8) as u8);
+            },
+            Argument::HighByte(ref expr) => {
+                let value = evaluate(expr, resolve, 0, 0xFFFF, "a address")?;
+                if value > 0xFF && value < 0xFF00 {
+                    return Err(out_of_range(expr, value, "a address from $FF00 to $FFFF"));
+                }
+                bytes.push(value as u8);
+            },
+            Argument::Relative(ref expr) => {
+                let target = evaluate(expr, resolve, 0, 0xFFFF, "a address")?;
+                bytes.push(relative_distance(target - end, expr.span())?);
+            },
+            Argument::RelativeOffset(offset) => {
+                bytes.push(relative_distance(address as i32 + offset - end, Span::default())?);
+            },
+            Argument::AbsoluteOffset(offset) => {
+                let target = address as i32 + offset;
+                bytes.push(target as u8);
+                bytes.push((target >> 8) as u8);
+            },
+            Argument::Vector(ref expr) => {
+                let value = evaluate(expr, resolve, 0, 0x38, "a RST vector")?;
+                if value % 8 != 0 {
+                    return Err(out_of_range(expr, value, "a RST vector ($00, $08, ..., $38)"));
+                }
+                bytes[0] |= value as u8;
+            },
+            Argument::BitIndex(ref expr) => {
+                let value = evaluate(expr, resolve, 0, 7, "a bit index from 0 to 7")?;
+                bytes[1] |= (value as u8) << 3;
+            }
+        }
+
+        Ok(bytes)
+
+    }
+
+}
+
+
+// Encoding -------------------------------------------------------------------
+fn encode(mnemonic: &str, operands: &[Operand]) -> Option<(Vec<u8>, Option<Argument>)> {
+
+    use self::Operand::*;
+
+    Some(match (mnemonic, operands) {
+
+        // Control
+        ("nop", []) => (vec![0x00], None),
+        ("stop", []) => (vec![0x10, 0x00], None),
+        ("halt", []) => (vec![0x76], None),
+        ("di", []) => (vec![0xF3], None),
+        ("ei", []) => (vec![0xFB], None),
+        ("daa", []) => (vec![0x27], None),
+        ("cpl", []) => (vec![0x2F], None),
+        ("cpl", [Register(ref r)]) if r == "a" => (vec![0x2F], None),
+        ("scf", []) => (vec![0x37], None),
+        ("ccf", []) => (vec![0x3F], None),
+        ("rlca", []) => (vec![0x07], None),
+        ("rrca", []) => (vec![0x0F], None),
+        ("rla", []) => (vec![0x17], None),
+        ("rra", []) => (vec![0x1F], None),
+
+        // Loads
+        ("ld", [ref dst, ref src]) => return encode_ld(dst, src),
+        ("ldh", [Address(ref addr), Register(ref r)]) if r == "a" => (vec![0xE0], Some(Argument::HighByte(addr.clone()))),
+        ("ldh", [Register(ref r), Address(ref addr)]) if r == "a" => (vec![0xF0], Some(Argument::HighByte(addr.clone()))),
+        ("ldh", [Memory(ref m), Register(ref r)]) if m == "c" && r == "a" => (vec![0xE2], None),
+        ("ldh", [Register(ref r), Memory(ref m)]) if m == "c" && r == "a" => (vec![0xF2], None),
+        ("ldi", [Memory(ref m), Register(ref r)]) if m == "hl" && r == "a" => (vec![0x22], None),
+        ("ldi", [Register(ref r), Memory(ref m)]) if m == "hl" && r == "a" => (vec![0x2A], None),
+        ("ldd", [Memory(ref m), Register(ref r)]) if m == "hl" && r == "a" => (vec![0x32], None),
+        ("ldd", [Register(ref r), Memory(ref m)]) if m == "hl" && r == "a" => (vec![0x3A], None),
+        ("ldhl", [Register(ref r), Value(ref value)]) if r == "sp" => (vec![0xF8], Some(Argument::SignedByte(value.clone()))),
+
+        // Arithmetic
+        ("add", [Register(ref r), ref src]) if r == "hl" => (vec![0x09 | register_pair(src, false)? << 4], None),
+        ("add", [Register(ref r), Value(ref value)]) if r == "sp" => (vec![0xE8], Some(Argument::SignedByte(value.clone()))),
+        ("add", _) | ("adc", _) | ("sub", _) | ("sbc", _) |
+        ("and", _) | ("xor", _) | ("or", _) | ("cp", _) => return encode_alu(mnemonic, operands),
+        ("inc", [ref op]) => match register(op) {
+            Some(r) => (vec![0x04 | r << 3], None),
+            None => (vec![0x03 | register_pair(op, false)? << 4], None)
+        },
+        ("dec", [ref op]) => match register(op) {
+            Some(r) => (vec![0x05 | r << 3], None),
+            None => (vec![0x0B | register_pair(op, false)? << 4], None)
+        },
+
+        // Jumps and calls
+        ("jp", [Register(ref r)]) | ("jp", [Memory(ref r)]) if r == "hl" => (vec![0xE9], None),
+        ("jp", [Value(ref target)]) => (vec![0xC3], Some(Argument::Word(target.clone()))),
+        ("jp", [Offset(offset)]) => (vec![0xC3], Some(Argument::AbsoluteOffset(*offset))),
+        ("jp", [ref cc, Value(ref target)]) => (vec![0xC2 | condition(cc)? << 3], Some(Argument::Word(target.clone()))),
+        ("jp", [ref cc, Offset(offset)]) => (vec![0xC2 | condition(cc)? << 3], Some(Argument::AbsoluteOffset(*offset))),
+        ("jr", [Value(ref target)]) => (vec![0x18], Some(Argument::Relative(target.clone()))),
+        ("jr", [Offset(offset)]) => (vec![0x18], Some(Argument::RelativeOffset(*offset))),
+        ("jr", [ref cc, Value(ref target)]) => (vec![0x20 | condition(cc)? << 3], Some(Argument::Relative(target.clone()))),
+        ("jr", [ref cc, Offset(offset)]) => (vec![0x20 | condition(cc)? << 3], Some(Argument::RelativeOffset(*offset))),
+        ("call", [Value(ref target)]) => (vec![0xCD], Some(Argument::Word(target.clone()))),
+        ("call", [Offset(offset)]) => (vec![0xCD], Some(Argument::AbsoluteOffset(*offset))),
+        ("call", [ref cc, Value(ref target)]) => (vec![0xC4 | condition(cc)? << 3], Some(Argument::Word(target.clone()))),
+        ("call", [ref cc, Offset(offset)]) => (vec![0xC4 | condition(cc)? << 3], Some(Argument::AbsoluteOffset(*offset))),
+        ("ret", []) => (vec![0xC9], None),
+        ("ret", [ref cc]) => (vec![0xC0 | condition(cc)? << 3], None),
+        ("reti", []) => (vec![0xD9], None),
+        ("rst", [Value(ref vector)]) => (vec![0xC7], Some(Argument::Vector(vector.clone()))),
+
+        // Stack
+        ("push", [ref op]) => (vec![0xC5 | register_pair(op, true)? << 4], None),
+        ("pop", [ref op]) => (vec![0xC1 | register_pair(op, true)? << 4], None),
+
+        // Bit operations
+        ("rlc", [ref op]) => (vec![0xCB, register(op)?], None),
+        ("rrc", [ref op]) => (vec![0xCB, 0x08 | register(op)?], None),
+        ("rl", [ref op]) => (vec![0xCB, 0x10 | register(op)?], None),
+        ("rr", [ref op]) => (vec![0xCB, 0x18 | register(op)?], None),
+        ("sla", [ref op]) => (vec![0xCB, 0x20 | register(op)?], None),
+        ("sra", [ref op]) => (vec![0xCB, 0x28 | register(op)?], None),
+        ("swap", [ref op]) => (vec![0xCB, 0x30 | register(op)?], None),
+        ("srl", [ref op]) => (vec![0xCB, 0x38 | register(op)?], None),
+        ("bit", [Value(ref index), ref op]) => (vec![0xCB, 0x40 | register(op)?], Some(Argument::BitIndex(index.clone()))),
+        ("res", [Value(ref index), ref op]) => (vec![0xCB, 0x80 | register(op)?], Some(Argument::BitIndex(index.clone()))),
+        ("set", [Value(ref index), ref op]) => (vec![0xCB, 0xC0 | register(op)?], Some(Argument::BitIndex(index.clone()))),
+
+        _ => return None
+
+    })
+
+}
+
+fn encode_ld(dst: &Operand, src: &Operand) -> Option<(Vec<u8>, Option<Argument>)> {
+
+    use self::Operand::*;
+
+    Some(match (dst, src) {
+
+        // "ld [hl], [hl]" would be the encoding of halt
+        (Memory(a), Memory(b)) if a == "hl" && b == "hl" => return None,
+        (_, Value(value)) if register(dst).is_some() => (vec![0x06 | register(dst)? << 3], Some(Argument::Byte(value.clone()))),
+        (_, _) if register(dst).is_some() && register(src).is_some() => (vec![0x40 | register(dst)? << 3 | register(src)?], None),
+
+        (Register(_), Value(value)) => (vec![0x01 | register_pair(dst, false)? << 4], Some(Argument::Word(value.clone()))),
+        (Memory(m), Register(r)) if r == "a" && m == "c" => (vec![0xE2], None),
+        (Register(r), Memory(m)) if r == "a" && m == "c" => (vec![0xF2], None),
+        (Memory(_), Register(r)) if r == "a" => (vec![0x02 | memory_pair(dst)? << 4], None),
+        (Register(r), Memory(_)) if r == "a" => (vec![0x0A | memory_pair(src)? << 4], None),
+        (Address(addr), Register(r)) if r == "sp" => (vec![0x08], Some(Argument::Word(addr.clone()))),
+        (Address(addr), Register(r)) if r == "a" => (vec![0xEA], Some(Argument::Word(addr.clone()))),
+        (Register(r), Address(addr)) if r == "a" => (vec![0xFA], Some(Argument::Word(addr.clone()))),
+        (Register(r), StackOffset(offset)) if r == "hl" => (vec![0xF8], Some(Argument::SignedByte(offset.clone()))),
+        (Register(a), Register(b)) if a == "sp" && b == "hl" => (vec![0xF9], None),
+
+        _ => return None
+
+    })
+
+}
+
+fn encode_alu(mnemonic: &str, operands: &[Operand]) -> Option<(Vec<u8>, Option<Argument>)> {
+
+    let index = match mnemonic {
+        "add" => 0,
+        "adc" => 1,
+        "sub" => 2,
+        "sbc" => 3,
+        "and" => 4,
+        "xor" => 5,
+        "or" => 6,
+        _ => 7
+    };
+
+    // The accumulator can be omitted as the first operand
+    let src = match operands {
+        [Operand::Register(ref r), ref src] if r == "a" => src,
+        [ref src] => src,
+        _ => return None
+    };
+
+    Some(match *src {
+        Operand::Value(ref value) => (vec![0xC6 | index << 3], Some(Argument::Byte(value.clone()))),
+        _ => (vec![0x80 | index << 3 | register(src)?], None)
+    })
+
+}
+
+/// Returns the encoding of a 8 bit register with [hl] at index 6
+fn register(operand: &Operand) -> Option<u8> {
+    match *operand {
+        Operand::Register(ref r) => ["b", "c", "d", "e", "h", "l", "", "a"].iter().position(|n| n == r).map(|i| i as u8),
+        Operand::Memory(ref m) if m == "hl" => Some(6),
+        _ => None
+    }
+}
+
+/// Returns the encoding of a 16 bit register, push and pop use "af" in
+/// place of "sp"
+fn register_pair(operand: &Operand, stack: bool) -> Option<u8> {
+    match *operand {
+        Operand::Register(ref r) => match &r[..] {
+            "bc" => Some(0),
+            "de" => Some(1),
+            "hl" => Some(2),
+            "sp" if !stack => Some(3),
+            "af" if stack => Some(3),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+/// Returns the encoding of a 16 bit register used as a memory address
+fn memory_pair(operand: &Operand) -> Option<u8> {
+    match *operand {
+        Operand::Memory(ref m) => match &m[..] {
+            "bc" => Some(0),
+            "de" => Some(1),
+            "hli" => Some(2),
+            "hld" => Some(3),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn condition(operand: &Operand) -> Option<u8> {
+    match *operand {
+        Operand::Register(ref r) => match &r[..] {
+            "nz" => Some(0),
+            "z" => Some(1),
+            "nc" => Some(2),
+            "c" => Some(3),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+
+// Timing ---------------------------------------------------------------------
+fn base_cycles(op: u8) -> usize {
+    match op {
+        0x76 => 4,
+
+        // Register loads and arithmetic, with [hl] as operand they need
+        // a additional memory access
+        0x40..=0xBF if op & 0x07 == 0x06 || (op < 0x80 && op & 0x38 == 0x30) => 8,
+        0x40..=0xBF => 4,
+
+        0x08 => 20,
+        0xCD => 24,
+        0x34 | 0x35 | 0x36 | 0xE0 | 0xF0 | 0xF8 | 0x18 => 12,
+        0xEA | 0xFA | 0xE8 | 0xC3 | 0xC9 | 0xD9 => 16,
+        0xE2 | 0xF2 | 0xF9 => 8,
+        _ => match op & 0xCF {
+            // ld rr, nn and pop
+            0x01 | 0xC1 => 12,
+            // push
+            0xC5 => 16,
+            // ld [rr], a and ld a, [rr], inc rr, dec rr, add hl, rr
+            0x02 | 0x0A | 0x03 | 0x0B | 0x09 => 8,
+            _ => match op & 0xC7 {
+                // rst
+                0xC7 => 16,
+                // ld r, n and arithmetic with a immediate value
+                0x06 | 0xC6 => 8,
+                _ => 4
+            }
+        }
+    }
+}
+
+fn cb_cycles(op: u8) -> usize {
+    match (op & 0x07 == 0x06, op & 0xC0 == 0x40) {
+        (false, _) => 8,
+        // bit only reads from [hl], all other instructions also write back
+        (true, true) => 12,
+        (true, false) => 16
+    }
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn is_register(expr: &Expression, register: &str) -> bool {
+    matches!(*expr, Expression::Name(ref name, _) if name == register)
+}
+
+fn evaluate<F>(expr: &Expression, resolve: &mut F, min: i32, max: i32, expected: &str) -> Result<i32, Diagnostic> where F: FnMut(&str) -> Option<Expression> {
+    match expr.evaluate(resolve) {
+        Expression::Number(value, _) => {
+            let value = value as i32;
+            if value < min || value > max {
+                Err(out_of_range(expr, value, expected))
+
+            } else {
+                Ok(value)
+            }
+        },
+        Expression::Invalid(diagnostic) => Err(diagnostic),
+        _ => Err(Diagnostic::error(
+            ErrorCode::InvalidOperands,
+            format!("Expected {} instead of a string", expected),
+            expr.span()
+        ))
+    }
+}
+
+fn out_of_range(expr: &Expression, value: i32, expected: &str) -> Diagnostic {
+    Diagnostic::error(
+        ErrorCode::OperandOutOfRange,
+        format!("Value {} is out of range, expected {}", value, expected),
+        expr.span()
+    )
+}
+
+fn relative_distance(distance: i32, span: Span) -> Result<u8, Diagnostic> {
+    if !(-128..=127).contains(&distance) {
+        Err(Diagnostic::error(
+            ErrorCode::OperandOutOfRange,
+            format!("Jump target is {} bytes away, jr can only jump from -128 to 127 bytes", distance),
+            span
+
+        ).with_note("use jp instead".to_string()))
+
+    } else {
+        Ok(distance as u8)
+    }
+}
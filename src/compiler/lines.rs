@@ -0,0 +1,87 @@
+use compiler::{Expansion, Section, SourceFile};
+
+/// A continuous range of ROM bytes produced by a single source line
+struct Range<'a> {
+    bank: usize,
+    start: usize,
+    end: usize,
+    file: usize,
+    line: usize,
+    expansions: &'a [Expansion]
+}
+
+/// Renders a table which maps every range of emitted ROM bytes back to the
+/// file and line that produced it
+///
+/// The table starts with all files by ID and their paths relative to the
+/// current directory, included files reference the file and line of their
+/// INCLUDE directive:
+///
+///   ; Files
+///   0 "src/main.gb.s"
+///   1 "src/hardware.inc" 0:3
+///
+/// Followed by the inclusive byte ranges sorted by bank and address. Code
+/// from macro expansions and REPT iterations maps to the line within their
+/// body, followed by the locations of all invocations starting with the
+/// innermost one:
+///
+///   ; Lines
+///   00:0150-00:0152 0:12
+///   00:0153-00:0154 1:4 0:20
+pub fn render_line_table(sections: &[Section], files: &[SourceFile]) -> String {
+
+    let mut lines = vec!["; File generated by gbasm".to_string(), "; Files".to_string()];
+    for file in files {
+        lines.push(match file.included_from {
+            Some(span) => format!("{} \"{}\" {}:{}", file.id, file.display_path(), span.file, span.line),
+            None => format!("{} \"{}\"", file.id, file.display_path())
+        });
+    }
+
+    let mut ranges: Vec<Range> = Vec::new();
+    for section in sections.iter().filter(|s| s.region.is_rom()) {
+        for entry in section.entries.iter().filter(|e| e.size() > 0) {
+
+            let start = section.address + entry.offset;
+            let end = start + entry.size() - 1;
+
+            // Merge entries of the same line which directly follow each other
+            if let Some(last) = ranges.last_mut() {
+                if last.bank == section.bank && last.end + 1 == start && last.file == entry.span.file
+                    && last.line == entry.span.line && last.expansions == &entry.expansions[..] {
+                    last.end = end;
+                    continue;
+                }
+            }
+
+            ranges.push(Range {
+                bank: section.bank,
+                start,
+                end,
+                file: entry.span.file,
+                line: entry.span.line,
+                expansions: &entry.expansions
+            });
+
+        }
+    }
+    ranges.sort_by_key(|r| (r.bank, r.start));
+
+    lines.push("; Lines".to_string());
+    for r in ranges {
+        let mut line = format!(
+            "{:0>2X}:{:0>4X}-{:0>2X}:{:0>4X} {}:{}",
+            r.bank, r.start, r.bank, r.end, r.file, r.line
+        );
+        for expansion in r.expansions {
+            line.push_str(&format!(" {}:{}", expansion.span.file, expansion.span.line));
+        }
+        lines.push(line);
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+
+}
+
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use compiler::{EntryKind, Expansion, Section, SourceFile};
+
+/// The number of bytes shown per row of the listing
+const BYTES_PER_ROW: usize = 4;
+
+/// The code emitted for a single source line
+struct Row {
+    bank: usize,
+    address: usize,
+    bytes: Vec<u8>,
+
+    /// Whether the bytes are only the start of a larger reserved space
+    elided: bool
+}
+
+/// A line of a macro expansion or REPT iteration, listed below the line
+/// which invoked it
+enum Expanded {
+    /// The start of a expansion at the given nesting depth
+    Header(usize, String),
+
+    /// The code of a line from the body at the given nesting depth
+    Code(usize, (usize, usize), Row)
+}
+
+/// Renders every source line next to the bank, address and bytes it
+/// assembled to, each file starts with a marker line
+///
+/// The code of macro expansions and REPT iterations is listed line by line
+/// below their outermost invocation, with a marker line at the start of
+/// every expansion.
+pub fn render_listing(sections: &[Section], files: &[SourceFile]) -> String {
+
+    // Collect the output of every line, entries on the same line always
+    // follow each other within a single section
+    let mut rows: HashMap<(usize, usize), Row> = HashMap::new();
+    let mut expanded: HashMap<(usize, usize), Vec<Expanded>> = HashMap::new();
+    let mut stacks: HashMap<(usize, usize), Vec<&Expansion>> = HashMap::new();
+    for section in sections {
+        for entry in &section.entries {
+
+            let bytes = section.data.get(entry.offset..entry.offset + entry.size()).unwrap_or(&[]);
+            let (bytes, elided) = match entry.kind {
+                EntryKind::Space(_, _) if bytes.len() > BYTES_PER_ROW => (&bytes[..BYTES_PER_ROW], true),
+                _ => (bytes, false)
+            };
+
+            let row = Row {
+                bank: section.bank,
+                address: section.address + entry.offset,
+                bytes: bytes.to_vec(),
+                elided
+            };
+
+            // Expanded code is grouped by the line of its outermost invocation
+            let line = (entry.span.file, entry.span.line);
+            let stack: Vec<&Expansion> = entry.expansions.iter().rev().collect();
+            let invocation = match stack.first() {
+                Some(expansion) => (expansion.span.file, expansion.span.line),
+                None => {
+                    let existing = rows.entry(line).or_insert(Row { bytes: vec![], elided: false, ..row });
+                    existing.bytes.extend_from_slice(bytes);
+                    existing.elided |= elided;
+                    continue;
+                }
+            };
+
+            let previous = stacks.entry(invocation).or_default();
+            let list = expanded.entry(invocation).or_default();
+            let common = previous.iter().zip(&stack).take_while(|&(a, b)| a == b).count();
+            for (depth, expansion) in stack.iter().enumerate().skip(common) {
+                list.push(Expanded::Header(depth, match expansion.iteration {
+                    Some(iteration) => format!("REPT iteration {}", iteration + 1),
+                    None => format!("macro \"{}\"", expansion.name)
+                }));
+            }
+
+            match list.last_mut() {
+                Some(&mut Expanded::Code(_, l, ref mut existing)) if l == line && common == stack.len() && previous.len() == stack.len() => {
+                    existing.bytes.extend_from_slice(bytes);
+                    existing.elided |= elided;
+                },
+                _ => list.push(Expanded::Code(stack.len(), line, row))
+            }
+            *previous = stack;
+
+        }
+    }
+
+    let mut lines = vec!["; File generated by gbasm".to_string()];
+    for file in files {
+
+        lines.push(String::new());
+        lines.push(match file.included_from.and_then(|span| files.get(span.file).map(|parent| (parent, span))) {
+            Some((parent, span)) => format!("; File \"{}\" (included from {}:{})", file.filename, parent.display_path(), span.line),
+            None => format!("; File \"{}\"", file.filename)
+        });
+
+        let mut sources = file.lines();
+        if sources.last().is_some_and(|l| l.is_empty()) {
+            sources.pop();
+        }
+
+        for (index, source) in sources.iter().enumerate() {
+
+            let line = index + 1;
+            match rows.get(&(file.id, line)) {
+                Some(row) => push_row(&mut lines, row, line, source),
+                None => lines.push(format!("{:<20} {:>5}  {}", "", line, source))
+            }
+
+            for row in expanded.get(&(file.id, line)).map_or(&[][..], |e| &e[..]) {
+                match *row {
+                    Expanded::Header(depth, ref text) => {
+                        lines.push(format!("{:<20} {:>5}  {}; {}", "", "", "  ".repeat(depth + 1), text));
+                    },
+                    Expanded::Code(depth, (body_file, body_line), ref row) => {
+                        let source = files.get(body_file).and_then(|f| f.line(body_line)).unwrap_or_default();
+                        let source = format!("{}{}", "  ".repeat(depth), source.trim_start());
+                        push_row(&mut lines, row, body_line, &source);
+                    }
+                }
+            }
+
+        }
+
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn push_row(lines: &mut Vec<String>, row: &Row, line: usize, source: &str) {
+
+    let mut chunks = row.bytes.chunks(BYTES_PER_ROW).map(format_bytes);
+    lines.push(format!(
+        "{:0>2X}:{:0>4X} {:<12} {:>5}  {}",
+        row.bank, row.address, chunks.next().unwrap_or_default(), line, source
+    ));
+
+    // Longer data continues on the following rows
+    for (index, chunk) in chunks.enumerate() {
+        let address = row.address + (index + 1) * BYTES_PER_ROW;
+        lines.push(format!("{:0>2X}:{:0>4X} {}", row.bank, address, chunk));
+    }
+
+    if row.elided {
+        lines.push(format!("{:0>2X}:{:0>4X} ...", row.bank, row.address + BYTES_PER_ROW));
+    }
+
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:0>2X}", b)).collect::<Vec<_>>().join(" ")
+}
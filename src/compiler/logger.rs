@@ -0,0 +1,56 @@
+use std::io::{Write, stderr};
+use std::time::Instant;
+
+/// How much information is logged during compilation
+#[derive(Debug, PartialEq, PartialOrd, Copy, Clone)]
+pub enum LogLevel {
+    /// Nothing but errors is reported
+    Silent,
+    Normal,
+    /// Additional details like timings and sizes are reported
+    Verbose
+}
+
+/// Writes progress messages to standard error so they never mix with output
+/// which is written to standard out
+pub struct Logger {
+    level: LogLevel
+}
+
+impl Logger {
+
+    pub fn new(level: LogLevel) -> Logger {
+        Logger {
+            level
+        }
+    }
+
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+
+    /// Logs a message unless logging is silenced
+    pub fn info(&self, message: &str) {
+        self.log(LogLevel::Normal, message);
+    }
+
+    /// Logs a message only in verbose mode
+    pub fn verbose(&self, message: &str) {
+        self.log(LogLevel::Verbose, message);
+    }
+
+    /// Logs how long a phase of the compilation took in verbose mode
+    pub fn timing(&self, phase: &str, started: Instant) {
+        if self.level >= LogLevel::Verbose {
+            let duration = started.elapsed();
+            self.verbose(&format!("{} took {:.2}ms", phase, duration.as_secs_f64() * 1000.0));
+        }
+    }
+
+    fn log(&self, level: LogLevel, message: &str) {
+        if self.level >= level {
+            writeln!(&mut stderr(), "{}", message).ok();
+        }
+    }
+
+}
@@ -0,0 +1,113 @@
+use compiler::{Region, Section};
+
+/// The number of characters used for the usage bar of a bank
+const BAR_WIDTH: usize = 32;
+
+/// Renders a overview of all regions and banks with their sections, free
+/// space and usage, followed by the totals for ROM, WRAM and HRAM
+pub fn render_map(sections: &[Section], rom_banks: usize) -> String {
+
+    let mut lines = vec!["; File generated by gbasm".to_string()];
+
+    // Used and available bytes for ROM, WRAM and HRAM
+    let mut totals = [("ROM", 0, 0), ("WRAM", 0, 0), ("HRAM", 0, 0)];
+
+    for region in Region::all() {
+
+        // All ROM banks are part of the image and WRAMX bank 1 is always
+        // available, other banks are only listed when they are used
+        let banks: Vec<usize> = match *region {
+            Region::Rom0 | Region::Wram0 | Region::Hram => vec![0],
+            Region::RomX => (1..rom_banks).collect(),
+            _ => {
+                let mut banks: Vec<usize> = sections.iter().filter(|s| s.region == *region).map(|s| s.bank).collect();
+                if *region == Region::WramX {
+                    banks.push(1);
+                }
+                banks.sort();
+                banks.dedup();
+                banks
+            }
+        };
+
+        for bank in banks {
+
+            let mut contents: Vec<&Section> = sections.iter().filter(|s| s.region == *region && s.bank == bank).collect();
+            contents.sort_by_key(|s| s.address);
+
+            lines.push(String::new());
+            lines.push(if region.banks().0 == region.banks().1 {
+                format!("{} ${:0>4X}-${:0>4X}", region.name(), region.start(), region.end() - 1)
+
+            } else {
+                format!("{}[{}] ${:0>4X}-${:0>4X}", region.name(), bank, region.start(), region.end() - 1)
+            });
+
+            let mut address = region.start();
+            let mut used = 0;
+            for section in contents {
+                if section.address > address {
+                    lines.push(format_range(address, section.address - address, "(free)"));
+                }
+                lines.push(format_range(section.address, section.size, &format!("\"{}\"", section.name)));
+                address = address.max(section.address + section.size);
+                used += section.size;
+            }
+
+            if address < region.end() {
+                lines.push(format_range(address, region.end() - address, "(free)"));
+            }
+
+            lines.push(format!("  {}  {}", format_bar(used, region.size()), format_usage(used, region.size())));
+
+            let total = match *region {
+                Region::Rom0 | Region::RomX => &mut totals[0],
+                Region::Wram0 | Region::WramX => &mut totals[1],
+                Region::Hram => &mut totals[2],
+                _ => continue
+            };
+            total.1 += used;
+            total.2 += region.size();
+
+        }
+
+    }
+
+    lines.push(String::new());
+    lines.push("Totals".to_string());
+    for (name, used, size) in totals.iter() {
+        lines.push(format!("  {:<5} {}", name, format_usage(*used, *size)));
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn format_range(address: usize, size: usize, name: &str) -> String {
+    format!(
+        "  ${:0>4X}-${:0>4X} {:>6} bytes  {}",
+        address, (address + size).max(address + 1) - 1, size, name
+    )
+}
+
+fn format_bar(used: usize, size: usize) -> String {
+
+    // Any used space is visible as at least one character
+    let mut filled = (used * BAR_WIDTH + size / 2) / size;
+    if used > 0 {
+        filled = filled.max(1);
+    }
+
+    format!("[{}{}]", "#".repeat(filled), ".".repeat(BAR_WIDTH - filled))
+
+}
+
+fn format_usage(used: usize, size: usize) -> String {
+    format!(
+        "{} / {} bytes used ({:.1}%), {} free",
+        used, size, used as f64 * 100.0 / size as f64, size.saturating_sub(used)
+    )
+}
@@ -1,10 +1,28 @@
-pub use self::source_iter::SourceIter;
-pub use self::source_file::SourceFile;
+pub use self::diagnostic::{Diagnostic, Diagnostics, ErrorCode, Expansion, Note, Severity, Warning};
+pub use self::explain::explain;
+pub use self::instruction::{Argument, Instruction, Operand};
+pub use self::logger::{Logger, LogLevel};
+pub use self::section::{Entry, EntryKind, Region, Section, Width};
+pub use self::source_iter::{SourceIter, SourcePosition};
+pub use self::source_file::{Include, SourceFile, relative_path};
 pub use self::source_string::SourceString;
+pub use self::symbol_table::{Macro, Symbol, SymbolKind, SymbolTable, label_name};
 pub use self::compiler::Compiler;
 
 pub mod source_iter;
+mod diagnostic;
+mod dump;
+mod explain;
+mod hex;
+mod instruction;
+mod json;
+mod lines;
+mod listing;
+mod logger;
+mod mapping;
+mod render;
+mod section;
 mod source_string;
 mod source_file;
+mod symbol_table;
 mod compiler;
-
@@ -0,0 +1,202 @@
+use compiler::{Diagnostic, Severity, SourceFile};
+use compiler::json::Json;
+use parser::Span;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders a diagnostic in the style of rustc, showing the underlined source
+/// lines of all its spans followed by the macro expansions, the include stack
+/// and any notes
+pub fn render_diagnostic(diagnostic: &Diagnostic, files: &[SourceFile], color: bool) -> String {
+
+    let paint = |style: &str, text: &str| -> String {
+        if color {
+            format!("{}{}{}", style, text, RESET)
+
+        } else {
+            text.to_string()
+        }
+    };
+
+    let (severity, style) = match diagnostic.severity {
+        Severity::Error => ("error", RED),
+        Severity::Warning => ("warning", YELLOW)
+    };
+
+    let mut output = format!(
+        "{}{}",
+        paint(style, &format!("{}[{}]", severity, diagnostic.code.as_str())),
+        paint(BOLD, &format!(": {}", diagnostic.message))
+    );
+
+    // Collect all spans which point into a known file
+    let mut snippets = vec![(diagnostic.span, "", true)];
+    snippets.extend(diagnostic.labels.iter().map(|&(span, ref label)| (span, &label[..], false)));
+    snippets.retain(|&(span, _, _)| span != Span::default() && span.file < files.len());
+
+    // Line numbers in the gutter are right aligned
+    let width = snippets.iter().map(|&(span, _, _)| span.line.to_string().len()).max().unwrap_or(0);
+    let gutter = " ".repeat(width);
+
+    for (index, &(span, label, primary)) in snippets.iter().enumerate() {
+
+        let file = &files[span.file];
+        output.push_str(&format!(
+            "\n{}{} {}:{}:{}",
+            gutter, paint(BLUE, if index == 0 { "-->" } else { ":::" }),
+            file.display_path(), span.line, span.column
+        ));
+
+        if let Some(line) = file.line(span.line) {
+
+            // Expand tabs so the markers line up with the source text
+            let column = span.column.saturating_sub(1).min(line.len());
+            let prefix = String::from_utf8_lossy(&line.as_bytes()[..column]);
+            let text = line.replace('\t', "    ");
+            let offset = prefix.replace('\t', "    ").chars().count();
+
+            // Multi line spans are underlined until the end of their first line
+            let length = if span.end > span.start {
+                span.end - span.start
+
+            } else {
+                1
+            };
+            let length = length.min(text.chars().count().saturating_sub(offset)).max(1);
+
+            let mut underline = if primary { "^" } else { "-" }.repeat(length);
+            if !label.is_empty() {
+                underline.push(' ');
+                underline.push_str(label);
+            }
+
+            output.push_str(&format!("\n{} {}", gutter, paint(BLUE, "|")));
+            output.push_str(&format!(
+                "\n{} {}",
+                paint(BLUE, &format!("{:>1$}", span.line, width)),
+                paint(BLUE, "|")
+            ));
+            if !text.is_empty() {
+                output.push_str(&format!(" {}", text));
+            }
+            output.push_str(&format!(
+                "\n{} {} {}{}",
+                gutter,
+                paint(BLUE, "|"),
+                " ".repeat(offset),
+                paint(if primary { style } else { BLUE }, &underline)
+            ));
+
+        }
+
+    }
+
+    // Show the macro invocations and REPT blocks which the primary span was
+    // expanded from
+    for expansion in diagnostic.expansions() {
+        if let Some(file) = files.get(expansion.span.file).filter(|_| expansion.span != Span::default()) {
+            let origin = match expansion.iteration {
+                Some(iteration) => format!("in iteration {} of REPT block at", iteration + 1),
+                None => format!("in expansion of macro \"{}\", invoked at", expansion.name)
+            };
+            output.push_str(&format!(
+                "\n{} {} {} {}:{}:{}",
+                gutter, paint(BLUE, "="), origin, file.display_path(), expansion.span.line, expansion.span.column
+            ));
+        }
+    }
+
+    // Show where the file of the primary span was included from
+    if let Some(&(span, _, true)) = snippets.first() {
+        let mut parent = files[span.file].included_from;
+        let mut depth = 0;
+        while let Some(include) = parent {
+            match files.get(include.file) {
+                Some(file) if depth < files.len() => {
+                    output.push_str(&format!(
+                        "\n{} {} included from {}:{}:{}",
+                        gutter, paint(BLUE, "="), file.display_path(), include.line, include.column
+                    ));
+                    parent = file.included_from;
+                    depth += 1;
+                },
+                _ => break
+            }
+        }
+    }
+
+    for note in diagnostic.text_notes() {
+        output.push_str(&format!("\n{} {} {} {}", gutter, paint(BLUE, "="), paint(BOLD, "note:"), note));
+    }
+
+    output
+
+}
+
+/// Renders a diagnostic as a single line JSON object for consumption by
+/// editors and other tools
+pub fn render_diagnostic_json(diagnostic: &Diagnostic, files: &[SourceFile]) -> String {
+
+    let severity = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning"
+    };
+
+    let mut entries = vec![
+        ("code", Json::string(diagnostic.code.as_str())),
+        ("severity", Json::string(severity)),
+        ("message", Json::string(&diagnostic.message))
+    ];
+    entries.extend(span_entries(diagnostic.span, files));
+
+    let labels = diagnostic.labels.iter().map(|&(span, ref label)| {
+        let mut entries = span_entries(span, files);
+        entries.push(("message", Json::string(label)));
+        Json::object(entries)
+
+    }).collect();
+
+    let expansions = diagnostic.expansions().into_iter().map(|expansion| {
+        let mut entries = vec![
+            ("macro", Json::string(&expansion.name)),
+            ("iteration", expansion.iteration.map_or(Json::Null, |i| Json::number(i as u32 + 1)))
+        ];
+        entries.extend(span_entries(expansion.span, files));
+        Json::object(entries)
+
+    }).collect();
+
+    entries.push(("labels", Json::Array(labels)));
+    entries.push(("expansions", Json::Array(expansions)));
+    entries.push(("notes", Json::Array(diagnostic.text_notes().into_iter().map(Json::string).collect())));
+    Json::object(entries).to_string()
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn span_entries(span: Span, files: &[SourceFile]) -> Vec<(&'static str, Json)> {
+    match files.get(span.file) {
+        Some(file) if span != Span::default() => {
+            let (end_line, end_column) = file.location(span.end);
+            vec![
+                ("file", Json::string(&file.display_path())),
+                ("line", Json::number(span.line as u32)),
+                ("column", Json::number(span.column as u32)),
+                ("end_line", Json::number(end_line as u32)),
+                ("end_column", Json::number(end_column as u32))
+            ]
+        },
+        _ => vec![
+            ("file", Json::Null),
+            ("line", Json::Null),
+            ("column", Json::Null),
+            ("end_line", Json::Null),
+            ("end_column", Json::Null)
+        ]
+    }
+}
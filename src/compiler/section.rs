@@ -0,0 +1,241 @@
+use compiler::{Expansion, Instruction, Operand};
+use parser::{Expression, Span};
+
+/// The memory regions of the GameBoy which sections can be placed in
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Region {
+    Rom0,
+    RomX,
+    Vram,
+    Sram,
+    Wram0,
+    WramX,
+    Oam,
+    Hram
+}
+
+const REGIONS: [Region; 8] = [
+    Region::Rom0,
+    Region::RomX,
+    Region::Vram,
+    Region::Sram,
+    Region::Wram0,
+    Region::WramX,
+    Region::Oam,
+    Region::Hram
+];
+
+impl Region {
+
+    /// Returns all regions in the order of their addresses
+    pub fn all() -> &'static [Region] {
+        &REGIONS
+    }
+
+    pub fn from_name(name: &str) -> Option<Region> {
+        REGIONS.iter().find(|r| r.name() == name).cloned()
+    }
+
+    /// Returns the name used in SECTION directives
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Region::Rom0 => "ROM0",
+            Region::RomX => "ROMX",
+            Region::Vram => "VRAM",
+            Region::Sram => "SRAM",
+            Region::Wram0 => "WRAM0",
+            Region::WramX => "WRAMX",
+            Region::Oam => "OAM",
+            Region::Hram => "HRAM"
+        }
+    }
+
+    /// The first address of the region
+    pub fn start(&self) -> usize {
+        match *self {
+            Region::Rom0 => 0x0000,
+            Region::RomX => 0x4000,
+            Region::Vram => 0x8000,
+            Region::Sram => 0xA000,
+            Region::Wram0 => 0xC000,
+            Region::WramX => 0xD000,
+            Region::Oam => 0xFE00,
+            Region::Hram => 0xFF80
+        }
+    }
+
+    /// The address directly after the end of the region
+    pub fn end(&self) -> usize {
+        match *self {
+            Region::Rom0 => 0x4000,
+            Region::RomX => 0x8000,
+            Region::Vram => 0xA000,
+            Region::Sram => 0xC000,
+            Region::Wram0 => 0xD000,
+            Region::WramX => 0xE000,
+            Region::Oam => 0xFEA0,
+            Region::Hram => 0xFFFF
+        }
+    }
+
+    /// The size of a single bank of the region in bytes
+    pub fn size(&self) -> usize {
+        self.end() - self.start()
+    }
+
+    /// The first and last bank which can be mapped into the region
+    pub fn banks(&self) -> (usize, usize) {
+        match *self {
+            Region::RomX => (1, 511),
+            Region::Vram => (0, 1),
+            Region::Sram => (0, 15),
+            Region::WramX => (1, 7),
+            _ => (0, 0)
+        }
+    }
+
+    /// Whether the contents of the region are stored in the ROM image
+    pub fn is_rom(&self) -> bool {
+        *self == Region::Rom0 || *self == Region::RomX
+    }
+
+}
+
+/// The storage size of a value which is only known after linking
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Width {
+    Byte,
+    Word
+}
+
+/// The contents of a section
+#[derive(Debug, PartialEq, Clone)]
+pub enum EntryKind {
+    Label(String),
+    Instruction(Instruction),
+
+    /// Data which is already known while parsing
+    Bytes(Vec<u8>),
+
+    /// A DB or DW value which refers to labels
+    Value(Expression, Width),
+
+    /// Space reserved via DS, optionally filled with a value
+    Space(usize, Option<u8>)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub kind: EntryKind,
+
+    /// The offset from the start of the section
+    pub offset: usize,
+
+    pub span: Span,
+
+    /// The macro expansions which produced the entry, starting with the
+    /// innermost one
+    pub expansions: Vec<Expansion>
+}
+
+impl Entry {
+
+    /// Returns the number of bytes the entry occupies
+    pub fn size(&self) -> usize {
+        match self.kind {
+            EntryKind::Label(_) => 0,
+            EntryKind::Instruction(ref instruction) => instruction.size(),
+            EntryKind::Bytes(ref bytes) => bytes.len(),
+            EntryKind::Value(_, Width::Byte) => 1,
+            EntryKind::Value(_, Width::Word) => 2,
+            EntryKind::Space(size, _) => size
+        }
+    }
+
+}
+
+/// A named block of code or data which the linker places into a region
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    pub name: String,
+    pub region: Region,
+
+    /// The address and bank requested in the SECTION directive
+    pub fixed_address: Option<usize>,
+    pub fixed_bank: Option<usize>,
+
+    /// The address and bank assigned by the linker
+    pub address: usize,
+    pub bank: usize,
+
+    pub entries: Vec<Entry>,
+    pub size: usize,
+
+    /// The linked contents, only filled for sections which are stored in
+    /// the ROM image
+    pub data: Vec<u8>,
+
+    pub span: Span
+}
+
+impl Section {
+
+    pub fn new(name: String, region: Region, fixed_address: Option<usize>, fixed_bank: Option<usize>, span: Span) -> Section {
+        Section {
+            name,
+            region,
+            fixed_address,
+            fixed_bank,
+            address: fixed_address.unwrap_or_else(|| region.start()),
+            bank: fixed_bank.unwrap_or_else(|| region.banks().0),
+            entries: vec![],
+            size: 0,
+            data: vec![],
+            span
+        }
+    }
+
+    /// Appends a entry at the current end of the section
+    pub fn add(&mut self, kind: EntryKind, span: Span) {
+        let entry = Entry {
+            kind,
+            offset: self.size,
+            span,
+            expansions: vec![]
+        };
+        self.size += entry.size();
+        self.entries.push(entry);
+    }
+
+    /// Returns all names which are still referenced by instruction arguments
+    /// and data values and need to be resolved by the linker
+    pub fn references(&self) -> Vec<String> {
+        let mut names = vec![];
+        for entry in &self.entries {
+            match entry.kind {
+                EntryKind::Instruction(ref instruction) => {
+                    for operand in &instruction.operands {
+                        match *operand {
+                            Operand::Address(ref expr) | Operand::Value(ref expr) |
+                            Operand::StackOffset(ref expr) => expr.visit_names(&mut |n| names.push(n.to_string())),
+                            _ => {}
+                        }
+                    }
+                },
+                EntryKind::Value(ref expr, _) => expr.visit_names(&mut |n| names.push(n.to_string())),
+                _ => {}
+            }
+        }
+        names
+    }
+
+    /// Returns the names and addresses of all labels within the section
+    pub fn labels(&self) -> Vec<(&str, usize, Span)> {
+        self.entries.iter().filter_map(|entry| match entry.kind {
+            EntryKind::Label(ref name) => Some((&name[..], self.address + entry.offset, entry.span)),
+            _ => None
+
+        }).collect()
+    }
+
+}
@@ -1,20 +1,59 @@
 use std::path::{Path, PathBuf};
+use std::env;
 use std::fs::File;
-use std::io::{Bytes, Read};
+use std::io::{self, Read};
 use std::iter;
+use std::mem;
 
-use compiler::SourceIter;
+use compiler::{Diagnostic, Diagnostics, EntryKind, ErrorCode, Expansion, Instruction, Logger, Macro, Operand, Region, Section, SourceIter, SourcePosition, SymbolKind, SymbolTable, Warning, Width};
+use parser::{interpolate, Expression, Span};
 use parser::Lexer;
 use parser::Token;
 
+/// How deep macro invocations and REPT blocks can be nested
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// The maximum number of iterations of a single REPT block
+const MAX_REPEAT_COUNT: usize = 65536;
+
+/// Returns a path relative to the current directory, paths outside of it
+/// are returned unchanged
+pub fn relative_path(path: &Path) -> String {
+    match env::current_dir() {
+        Ok(cwd) => path.strip_prefix(cwd).unwrap_or(path).display().to_string(),
+        Err(_) => path.display().to_string()
+    }
+}
+
+/// Loads the files referenced by INCLUDE and INCBIN directives
+pub trait Include {
+
+    /// Parses another source file in place of the directive, returning the
+    /// section which is open at its end
+    fn include(
+        &mut self,
+        path: &str,
+        span: Span,
+        symbols: &mut SymbolTable,
+        diagnostics: &mut Diagnostics,
+        sections: &mut Vec<Section>,
+        section: Option<usize>
+
+    ) -> Result<Option<usize>, Diagnostic>;
+
+    /// Reads the contents of a binary file
+    fn include_binary(&mut self, path: &str, span: Span) -> Result<Vec<u8>, Diagnostic>;
+
+}
+
 /// A struct which represents an assembly source from a file on disk
 pub struct SourceFile<'a> {
 
     /// An optional parent file which included this file
     pub parent: Option<&'a SourceFile<'a>>,
 
-    /// A ID used for debugging purposes
-    pub id: i32,
+    /// A ID used to identify the file in spans
+    pub id: usize,
 
     /// The path to the file without the filename
     pub path: String,
@@ -22,9 +61,14 @@ pub struct SourceFile<'a> {
     /// The file's filename without the leading path
     pub filename: String,
 
-    bytes: iter::Peekable<Bytes<File>>,
+    /// The location of the directive which included this file
+    pub included_from: Option<Span>,
+
+    bytes: iter::Peekable<Box<dyn Iterator<Item = io::Result<u8>>>>,
+    source: Vec<u8>,
     last: u8,
-    empty: bool
+    empty: bool,
+    position: SourcePosition
 }
 
 impl <'a>SourceFile<'a> {
@@ -38,34 +82,1289 @@ impl <'a>SourceFile<'a> {
                 id: 0,
                 path: path.parent().unwrap_or(Path::new("")).to_str().unwrap().to_string(),
                 filename: path.file_name().unwrap().to_str().unwrap().to_string(),
-                bytes: file.bytes().peekable(),
+                included_from: None,
+                bytes: (Box::new(file.bytes()) as Box<dyn Iterator<Item = io::Result<u8>>>).peekable(),
+                source: vec![],
                 last: 0,
-                empty: false
+                empty: false,
+                position: SourcePosition::default()
             }),
             Err(err) => Err(format!("Failed to open file \"{}\": {}", filepath, err))
         }
 
     }
 
-    pub fn parse(&mut self) {
+    /// Creates a file from source text which was read before, e.g. from a
+    /// object file, so locations within it can still be displayed
+    pub fn from_source(path: String, filename: String, source: Vec<u8>) -> SourceFile<'a> {
+        SourceFile {
+            parent: None,
+            id: 0,
+            path,
+            filename,
+            included_from: None,
+            bytes: (Box::new(iter::empty()) as Box<dyn Iterator<Item = io::Result<u8>>>).peekable(),
+            source,
+            last: 0,
+            empty: true,
+            position: SourcePosition::default()
+        }
+    }
 
-        let mut lexer = Lexer::new(self).peekable();
+    /// Returns the path of the file relative to the current directory
+    pub fn display_path(&self) -> String {
+        relative_path(&Path::new(&self.path).join(&self.filename))
+    }
 
-        loop {
-            match lexer.next().unwrap() {
-                Token::Eof => {
-                    break;
+    /// Returns all bytes which were read from the file so far
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Returns the contents of a line (starting at 1) which was already read
+    /// from the file
+    pub fn line(&self, line: usize) -> Option<String> {
+        self.source.split(|b| *b == b'\n').nth(line.wrapping_sub(1)).map(|bytes| {
+            String::from_utf8_lossy(bytes).trim_end_matches('\r').to_string()
+        })
+    }
+
+    /// Returns the contents of all lines which were already read from the
+    /// file
+    pub fn lines(&self) -> Vec<String> {
+        self.source.split(|b| *b == b'\n').map(|bytes| {
+            String::from_utf8_lossy(bytes).trim_end_matches('\r').to_string()
+
+        }).collect()
+    }
+
+    /// Returns the line and column (both starting at 1) of a byte offset
+    /// which was already read from the file
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        let bytes = &self.source[..offset.min(self.source.len())];
+        match bytes.iter().rposition(|b| *b == b'\n') {
+            Some(index) => (bytes.iter().filter(|b| **b == b'\n').count() + 1, offset - index),
+            None => (1, offset + 1)
+        }
+    }
+
+    /// Parses the file, reporting all errors and warnings to the passed list
+    /// of diagnostics
+    ///
+    /// After an error the rest of the line is skipped and parsing continues
+    /// with the next one until the error limit is reached.
+    ///
+    /// Included files continue within the section and label scope of the
+    /// including file, the section which is open at the end is returned.
+    pub fn parse(
+        &mut self,
+        symbols: &mut SymbolTable,
+        diagnostics: &mut Diagnostics,
+        sections: &mut Vec<Section>,
+        section: Option<usize>,
+        logger: &Logger,
+        include: &mut dyn Include
+
+    ) -> Option<usize> {
+
+        let filename = self.filename.clone();
+        symbols.set_builtin("__FILE__", Expression::String(filename.clone(), Span::default()));
+
+        // Every top level file has to open its own section before emitting
+        // any code
+        if self.included_from.is_none() {
+            symbols.unset_builtin("__SECTION__");
+            symbols.set_scope(None);
+        }
+
+        let mut parser = Parser {
+            symbols,
+            diagnostics,
+            sections,
+            section,
+            logger,
+            include,
+            filename,
+            jump: None
+        };
+
+        parser.parse_statements(&mut Lexer::new(self).peekable());
+        parser.section
+
+    }
+
+}
+
+/// Parses the statements of a file together with the bodies of all macros
+/// which are invoked within it
+struct Parser<'p> {
+    symbols: &'p mut SymbolTable,
+    diagnostics: &'p mut Diagnostics,
+    sections: &'p mut Vec<Section>,
+
+    /// The section which code is currently emitted into
+    section: Option<usize>,
+
+    logger: &'p Logger,
+    include: &'p mut dyn Include,
+    filename: String,
+
+    /// The last unconditional jump or return, instructions following it
+    /// are unreachable until the next label
+    jump: Option<Span>
+}
+
+impl<'p> Parser<'p> {
+
+    /// Parses statements until the tokens end or the error limit is reached,
+    /// after an error the rest of the line is skipped
+    fn parse_statements<I>(&mut self, lexer: &mut iter::Peekable<I>) where I: Iterator<Item = (Token, Span)> {
+
+        while !self.diagnostics.limit_reached() {
+
+            let (token, span) = match lexer.next() {
+                Some((Token::Eof, _)) | None => break,
+                Some(token) => token
+            };
+            self.symbols.set_builtin("__LINE__", Expression::Number(span.line as f32, span));
+
+            // Within floating sections the address of the statement is only
+            // known after linking, so __ADDRESS__ is resolved by the linker
+            match self.section.map(|i| &self.sections[i]) {
+                Some(s) if s.fixed_address.is_some() => {
+                    self.symbols.set_builtin("__ADDRESS__", Expression::Number((s.address + s.size) as f32, span));
+                },
+                _ => self.symbols.unset_builtin("__ADDRESS__")
+            }
+
+            let entries = self.section.map(|i| (i, self.sections[i].entries.len()));
+            if let Err(diagnostic) = self.parse_statement(lexer, token, span) {
+                self.diagnostics.report(diagnostic);
+                skip_line(lexer);
+            }
+
+            // Code from within macros keeps the trace of its expansions,
+            // entries of nested expansions were already marked
+            let trace = self.diagnostics.expansion_trace();
+            if let Some((index, start)) = entries.filter(|_| !trace.is_empty()) {
+                for entry in self.sections[index].entries.iter_mut().skip(start) {
+                    if entry.expansions.is_empty() {
+                        entry.expansions = trace.clone();
+                    }
+                }
+            }
+
+        }
+
+    }
+
+    fn parse_statement<I>(
+        &mut self,
+        lexer: &mut iter::Peekable<I>,
+        token: Token,
+        span: Span
+
+    ) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+        let symbols = &mut *self.symbols;
+        let diagnostics = &mut *self.diagnostics;
+        let sections = &mut *self.sections;
+        let jump = &mut self.jump;
+
+        match token {
+            Token::Error(diagnostic) => Err(diagnostic),
+
+            // Constant and variable definitions, any other names are
+            // macro invocations
+            Token::Name(name) => match lexer.peek() {
+                Some(&(Token::Directive(ref d), _)) if is_definition(d) => {
+                    let directive = d.to_string();
+                    parse_definition(lexer, symbols, name, span, directive)
+                },
+                _ => self.expand_macro(lexer, name, span)
+            },
+
+            Token::Macro(name) => parse_macro(lexer, symbols, diagnostics, name, span),
+
+            Token::GlobalLabelDef(name) => {
+                *jump = None;
+                current_section(sections, self.section, "Labels", span, false).and_then(|s| {
+                    parse_label(symbols, diagnostics, name, span, false).map(|name| s.add(EntryKind::Label(name), span))
+                })
+            },
+
+            Token::LocalLabelDef(name) => {
+                *jump = None;
+                current_section(sections, self.section, "Labels", span, false).and_then(|s| {
+                    parse_label(symbols, diagnostics, name, span, true).map(|name| s.add(EntryKind::Label(name), span))
+                })
+            },
+
+            Token::Instruction(name) => current_section(sections, self.section, "Instructions", span, true).and_then(|s| {
+                parse_instruction(lexer, symbols, diagnostics, s, name, span, jump)
+            }),
+
+            Token::Directive(directive) => match &directive[..] {
+                "SECTION" => {
+                    let index = parse_section(lexer, symbols, sections, span)?;
+                    *jump = None;
+                    self.section = Some(index);
+                    Ok(())
+                },
+                "DB" | "DW" => current_section(sections, self.section, "Data", span, true).and_then(|s| {
+                    parse_data(lexer, symbols, diagnostics, s, directive == "DB")
+                }),
+                "DS" => current_section(sections, self.section, "Reserved space", span, false).and_then(|s| {
+                    parse_space(lexer, symbols, s, span)
+                }),
+                "INCLUDE" => {
+                    let (path, _) = parse_path(lexer, symbols, &directive, span)?;
+                    let section = self.include.include(&path, span, symbols, diagnostics, sections, self.section);
+                    symbols.set_builtin("__FILE__", Expression::String(self.filename.clone(), Span::default()));
+                    self.section = section?;
+                    *jump = None;
+                    Ok(())
+                },
+                "INCBIN" => {
+                    let include = &mut *self.include;
+                    current_section(sections, self.section, "Binary data", span, true).and_then(|s| {
+                        parse_binary(lexer, symbols, include, s, span)
+                    })
+                },
+                "REPT" => self.repeat(lexer, span),
+                "ENDR" => Err(Diagnostic::error(
+                    ErrorCode::UnexpectedRepeatEnd,
+                    "Unexpected ENDR without a matching REPT".to_string(),
+                    span
+                )),
+                "NOWARN" => parse_no_warn(lexer, diagnostics, span),
+                "EXPORT" => parse_export(lexer, symbols),
+                "ENDNOWARN" => {
+                    if diagnostics.end_suppression(span) {
+                        Ok(())
+
+                    } else {
+                        Err(Diagnostic::error(
+                            ErrorCode::UnexpectedNoWarnEnd,
+                            "Unexpected ENDNOWARN without a matching NOWARN".to_string(),
+                            span
+                        ))
+                    }
+                },
+                _ => {
+                    self.logger.verbose(&format!("Skipping unsupported {} directive ({}:{})", directive, span.line, span.column));
+                    skip_line(lexer);
+                    Ok(())
+                }
+            },
+
+            Token::Newline => Ok(()),
+            token => {
+                self.logger.verbose(&format!("Skipping unsupported {:?} ({}:{})", token.to_type(), span.line, span.column));
+                Ok(())
+            }
+        }
+
+    }
+
+    /// Parses the body of a macro in place of its invocation, with all of
+    /// its parameters replaced by the arguments which follow the name
+    fn expand_macro<I>(
+        &mut self,
+        lexer: &mut iter::Peekable<I>,
+        name: String,
+        span: Span
+
+    ) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+        let operands = read_operands(lexer)?;
+        self.symbols.reference(&name);
+        reference_operands(self.symbols, &operands);
+
+        let (definition, definition_span) = match (self.symbols.get_macro(&name), self.symbols.get(&name)) {
+            (Some(definition), Some(symbol)) => (definition.clone(), symbol.span),
+            _ => return Err(Diagnostic::error(
+                ErrorCode::UnknownMacro,
+                format!("Unknown instruction, directive or macro \"{}\"", name),
+                span
+            ))
+        };
+
+        let statement = operands.iter().fold(span, |statement, &(_, s)| statement.merge(s));
+        let args = parse_macro_args(operands)?;
+        if args.len() != definition.params.len() {
+            return Err(Diagnostic::error(
+                ErrorCode::MacroArguments,
+                format!(
+                    "Macro \"{}\" takes {} argument(s) but {} were given",
+                    name, definition.params.len(), args.len()
+                ),
+                statement
+
+            ).with_label(definition_span, "macro defined here"));
+
+        } else if self.diagnostics.expansion_trace().len() >= MAX_EXPANSION_DEPTH {
+            return Err(Diagnostic::error(
+                ErrorCode::ExpansionDepth,
+                format!("Cannot expand macro \"{}\", macros are nested too deep", name),
+                statement
+            ));
+        }
+
+        let mut resolve = |n: &str| {
+            definition.params.iter().position(|p| n.strip_prefix('@') == Some(&p[..])).map(|i| args[i].clone())
+        };
+
+        let body: Vec<(Token, Span)> = definition.body.iter().map(|&(ref token, span)| {
+            let token = match *token {
+                Token::Expression(ref expr) => Token::Expression(expr.substitute(&mut resolve)),
+                Token::MacroArg(ref name) => match resolve(&format!("@{}", name)) {
+                    Some(value) => Token::Expression(value.with_span(span)),
+                    None => token.clone()
                 },
-                Token::Error(ref err) => {
-                    println!("Error: {}", err);
-                    break;
+
+                // Labels can contain their arguments via "{@name}"
+                Token::GlobalLabelDef(ref name) => Token::GlobalLabelDef(interpolate(name, &mut resolve, span).unwrap_or_else(|_| name.clone())),
+                Token::LocalLabelDef(ref name) => Token::LocalLabelDef(interpolate(name, &mut resolve, span).unwrap_or_else(|_| name.clone())),
+                ref token => token.clone()
+            };
+            (token, span)
+
+        }).collect();
+
+        self.diagnostics.enter_expansion(Expansion {
+            name,
+            span: statement,
+            iteration: None
+        });
+        self.parse_statements(&mut body.into_iter().peekable());
+        self.diagnostics.leave_expansion();
+        Ok(())
+
+    }
+
+    /// Parses the body of a REPT block once for every iteration, nested
+    /// blocks are repeated as part of their parent's body
+    fn repeat<I>(
+        &mut self,
+        lexer: &mut iter::Peekable<I>,
+        span: Span
+
+    ) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+        // Errors in the count are only returned once the body was read
+        let operands = read_operands(lexer)?;
+        let statement = operands.iter().fold(span, |statement, &(_, s)| statement.merge(s));
+        let count = match &operands[..] {
+            [(Token::Expression(ref count), _)] => evaluate_number(count, self.symbols),
+            _ => Err(Diagnostic::error(
+                ErrorCode::ExpectedValue,
+                "Expected a count after REPT directive".to_string(),
+                span
+            ))
+        };
+
+        let mut body = vec![];
+        let mut depth = 0;
+        loop {
+
+            if let Some(&(Token::Eof, end)) = lexer.peek() {
+                return Err(Diagnostic::error(
+                    ErrorCode::UnclosedRepeat,
+                    "REPT block is missing its ENDR directive".to_string(),
+                    span
+
+                ).with_label(end, "file ends here"));
+            }
+
+            match lexer.next() {
+                Some((Token::Directive(ref d), _)) if d == "ENDR" && depth == 0 => break,
+                Some((token, token_span)) => {
+                    match token {
+                        Token::Directive(ref d) if d == "REPT" => depth += 1,
+                        Token::Directive(ref d) if d == "ENDR" => depth -= 1,
+                        _ => {}
+                    }
+                    body.push((token, token_span));
                 },
-                token => println!("{:?}", token)
+                None => return Err(Diagnostic::error(
+                    ErrorCode::UnclosedRepeat,
+                    "REPT block is missing its ENDR directive".to_string(),
+                    span
+                ))
+            }
+
+        }
+
+        let count = match count? {
+            count if count >= 0 && count as usize <= MAX_REPEAT_COUNT => count as usize,
+            count => return Err(Diagnostic::error(
+                ErrorCode::ExpectedValue,
+                format!("Invalid count {} for REPT directive, expected 0 to {}", count, MAX_REPEAT_COUNT),
+                statement
+            ))
+        };
+
+        if self.diagnostics.expansion_trace().len() >= MAX_EXPANSION_DEPTH {
+            return Err(Diagnostic::error(
+                ErrorCode::ExpansionDepth,
+                "Cannot repeat REPT block, macros and REPT blocks are nested too deep".to_string(),
+                statement
+            ));
+        }
+
+        for iteration in 0..count {
+            if self.diagnostics.limit_reached() {
+                break;
+            }
+            self.diagnostics.enter_expansion(Expansion {
+                name: "REPT".to_string(),
+                span: statement,
+                iteration: Some(iteration)
+            });
+            self.parse_statements(&mut body.clone().into_iter().peekable());
+            self.diagnostics.leave_expansion();
+        }
+        Ok(())
+
+    }
+
+}
+
+fn parse_definition<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    name: String,
+    span: Span,
+    directive: String
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    let (_, directive_span) = lexer.next().unwrap();
+
+    // Values are always evaluated eagerly so variables can
+    // refer to their own previous value
+    let (expr, value) = match lexer.peek() {
+        Some(&(Token::Expression(ref expr), _)) => (Some(expr.clone()), Some(expr.evaluate(&mut |n| symbols.resolve(n)))),
+        Some(&(Token::Error(ref diagnostic), _)) => (None, Some(Expression::Invalid(diagnostic.clone()))),
+        _ => (None, None)
+    };
+
+    let value = match value {
+        Some(value) => {
+            lexer.next();
+            value
+        },
+        None => Expression::Invalid(Diagnostic::error(
+            ErrorCode::ExpectedValue,
+            format!("Expected value after {} directive", directive),
+            directive_span
+        ))
+    };
+
+    let kind = match &directive[..] {
+        "SET" | "=" => SymbolKind::Variable,
+        _ => SymbolKind::Constant
+    };
+
+    match (interpolate(&name, &mut |n| symbols.resolve(n), span), value, expr) {
+
+        // Constants can refer to names from other object files, which are
+        // checked once all sources were parsed
+        (Ok(name), Expression::Invalid(ref diagnostic), Some(ref expr)) if directive == "EQU" && is_deferred(diagnostic.code) => {
+            match read_operands(lexer)?.into_iter().next() {
+                Some((token, token_span)) => Err(unexpected_token(token, token_span)),
+                None => symbols.define_deferred(&name, expr, span)
+            }
+        },
+        (Err(diagnostic), _, _) | (_, Expression::Invalid(diagnostic), _) => Err(diagnostic),
+        (Ok(name), Expression::Number(_, value_span), _) if directive == "EQUS" => {
+            Err(Diagnostic::error(
+                ErrorCode::ExpectedString,
+                format!("Expected string value for EQUS constant \"{}\"", name),
+                span
+
+            ).with_label(value_span, "value is a number"))
+        },
+
+        // Only a single value can follow the directive
+        (Ok(name), value, _) => match read_operands(lexer)?.into_iter().next() {
+            Some((token, token_span)) => Err(unexpected_token(token, token_span)),
+            None => symbols.define(&name, kind, value, span)
+        }
+    }
+
+}
+
+fn parse_label(
+    symbols: &mut SymbolTable,
+    diagnostics: &mut Diagnostics,
+    name: String,
+    span: Span,
+    is_local: bool
+
+) -> Result<String, Diagnostic> {
+
+    let name = interpolate(&name, &mut |n| symbols.resolve(n), span)?;
+    let name = if is_local {
+
+        let current = symbols.qualify(&name);
+        let name = match symbols.scope() {
+            Some(scope) => format!("{}{}", scope, name),
+            None => return Err(Diagnostic::error(
+                ErrorCode::LocalLabelWithoutScope,
+                format!("Local label \"{}\" is defined before any global label", name),
+                span
+            ))
+        };
+
+        // Local labels shadow earlier ones with the same name, references
+        // which were already made keep referring to the earlier label
+        match symbols.get(&current).filter(|s| s.kind == SymbolKind::Label).map(|s| s.span) {
+            Some(previous) => {
+                diagnostics.report(Diagnostic::warning(
+                    Warning::Shadow,
+                    format!("Local label \"{}\" shadows a earlier label with the same name", name),
+                    span
+
+                ).with_label(previous, "previously defined here"));
+                symbols.shadow(&name)
+            },
+            None => name
+        }
+
+    } else {
+        symbols.set_scope(Some(name.clone()));
+        name
+    };
+
+    // The address of a label is only known once all sections are linked
+    let value = Expression::Invalid(Diagnostic::error(
+        ErrorCode::UnresolvedLabel,
+        format!("The address of label \"{}\" is not known before linking", name),
+        span
+    ));
+
+    symbols.define(&name, SymbolKind::Label, value, span)?;
+    Ok(name)
+
+}
+
+fn parse_instruction<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    diagnostics: &mut Diagnostics,
+    section: &mut Section,
+    name: String,
+    span: Span,
+    jump: &mut Option<Span>
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    // Split the operands at their commas
+    let mut operands = vec![];
+    let mut tokens = vec![];
+    let mut statement = span;
+    for (token, token_span) in read_operands(lexer)? {
+        statement = statement.merge(token_span);
+        if token == Token::Comma {
+            operands.push(Operand::parse(mem::take(&mut tokens), token_span)?);
+
+        } else {
+            tokens.push((token, token_span));
+        }
+    }
+
+    if !tokens.is_empty() {
+        operands.push(Operand::parse(tokens, span)?);
+    }
+
+    let operands: Vec<Operand> = operands.into_iter().map(|operand| {
+        operand.map(&mut |expr| expr.substitute(&mut |n| resolve_static(symbols, n)))
+
+    }).collect();
+
+    let instruction = Instruction::new(&name, operands, statement)?;
+
+    if let Some(previous) = jump.take() {
+        diagnostics.report(Diagnostic::warning(
+            Warning::Unreachable,
+            format!("Unreachable \"{}\" instruction", name),
+            span
+
+        ).with_label(previous, "any code following this instruction is unreachable"));
+    }
+
+    if name == "ldhl" {
+        diagnostics.report(Diagnostic::warning(
+            Warning::Deprecated,
+            "The \"ldhl\" instruction is deprecated".to_string(),
+            span
+
+        ).with_note("use \"ld hl, sp + offset\" instead".to_string()));
+    }
+
+    // Jumps and returns without a condition never continue with the next
+    // instruction
+    let is_conditional = match instruction.operands.first() {
+        Some(Operand::Register(operand)) => is_condition(operand),
+        _ => false
+    };
+
+    if matches!(&name[..], "jp" | "jr" | "ret" | "reti") && !is_conditional {
+        *jump = Some(span);
+    }
+
+    section.add(EntryKind::Instruction(instruction), statement);
+    Ok(())
+
+}
+
+fn parse_data<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    diagnostics: &mut Diagnostics,
+    section: &mut Section,
+    is_byte: bool
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    // Values and commas have to alternate
+    let mut comma: Option<Span> = None;
+    let mut expect_value = true;
+    for (token, span) in read_operands(lexer)? {
+        match token {
+            Token::Comma if expect_value => {
+                return Err(Diagnostic::error(ErrorCode::MissingOperand, "Expected a value before \",\"".to_string(), span));
+            },
+            Token::Comma => {
+                comma = Some(span);
+                expect_value = true;
+            },
+            Token::Expression(_) if !expect_value => {
+                return Err(Diagnostic::error(ErrorCode::UnexpectedToken, "Expected \",\" between values".to_string(), span));
+            },
+            Token::Expression(expr) => {
+
+                expect_value = false;
+
+                // Values referring to labels can only be stored after linking
+                let expr = expr.substitute(&mut |n| resolve_static(symbols, n));
+                match expr.evaluate(&mut |_| None) {
+                    Expression::Number(value, span) => {
+                        let (min, max, storage) = if is_byte {
+                            (-128.0, 255.0, "byte")
+
+                        } else {
+                            (-32768.0, 65535.0, "word")
+                        };
+                        if value < min || value > max {
+                            diagnostics.report(Diagnostic::warning(
+                                Warning::Truncation,
+                                format!("Value {} does not fit into a {} and will be truncated", value, storage),
+                                span
+                            ));
+                        }
+
+                        let value = value as i32;
+                        section.add(EntryKind::Bytes(if is_byte {
+                            vec![value as u8]
+
+                        } else {
+                            vec![value as u8, (value >> 8) as u8]
+
+                        }), span);
+                    },
+                    Expression::String(value, span) => {
+                        section.add(EntryKind::Bytes(if is_byte {
+                            value.into_bytes()
+
+                        } else {
+                            value.bytes().flat_map(|b| vec![b, 0]).collect()
+
+                        }), span);
+                    },
+                    Expression::Invalid(diagnostic) => match diagnostic.code {
+                        ErrorCode::UndefinedName => {
+                            let width = if is_byte { Width::Byte } else { Width::Word };
+                            section.add(EntryKind::Value(expr, width), span);
+                        },
+                        _ => return Err(diagnostic)
+                    },
+                    _ => {}
+                }
+
+            },
+            token => return Err(unexpected_token(token, span))
+        }
+    }
+
+    match comma {
+        Some(span) if expect_value => {
+            Err(Diagnostic::error(ErrorCode::MissingOperand, "Expected a value after \",\"".to_string(), span))
+        },
+        _ => Ok(())
+    }
+
+}
+
+fn parse_section<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    sections: &mut Vec<Section>,
+    span: Span
+
+) -> Result<usize, Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    let mut tokens = read_operands(lexer)?.into_iter().peekable();
+
+    let name = match tokens.next() {
+        Some((Token::Expression(expr), name_span)) => match expr.evaluate(&mut |n| symbols.resolve(n)) {
+            Expression::String(name, _) => name,
+            Expression::Invalid(diagnostic) => return Err(diagnostic),
+            _ => return Err(invalid_section("Expected a string as the section name".to_string(), name_span))
+        },
+        _ => return Err(invalid_section("Expected a section name after SECTION directive".to_string(), span))
+    };
+
+    let region = match (tokens.next(), tokens.next()) {
+        (Some((Token::Comma, _)), Some((Token::Expression(Expression::Name(region, region_span)), _))) |
+        (Some((Token::Comma, _)), Some((Token::Name(region), region_span))) => match Region::from_name(&region) {
+            Some(region) => region,
+            None => return Err(invalid_section(format!("Unknown region \"{}\"", region), region_span).with_note(
+                "valid regions are ROM0, ROMX, VRAM, SRAM, WRAM0, WRAMX, OAM and HRAM".to_string()
+            ))
+        },
+        _ => return Err(invalid_section("Expected a region after the section name".to_string(), span))
+    };
+
+    let address = parse_bracket_value(&mut tokens, symbols)?;
+    let bank = match tokens.next() {
+        Some((Token::Comma, _)) => match tokens.next() {
+            Some((Token::Directive(ref d), bank_span)) if d == "BANK" => match parse_bracket_value(&mut tokens, symbols)? {
+                Some(bank) => Some(bank),
+                None => return Err(invalid_section("Expected a bank number after BANK".to_string(), bank_span))
+            },
+            _ => return Err(invalid_section("Expected BANK after the section region".to_string(), span))
+        },
+        Some((token, span)) => return Err(unexpected_token(token, span)),
+        None => None
+    };
+
+    if let Some((token, span)) = tokens.next() {
+        return Err(unexpected_token(token, span));
+    }
+
+    if let Some((address, address_span)) = address {
+        if address < region.start() || address >= region.end() {
+            return Err(invalid_section(format!(
+                "Address ${:0>4X} is outside of the {} region (${:0>4X}-${:0>4X})",
+                address, region.name(), region.start(), region.end() - 1
+
+            ), address_span));
+        }
+    }
+
+    if let Some((bank, bank_span)) = bank {
+        let (first, last) = region.banks();
+        if first == last {
+            return Err(invalid_section(format!("{} sections cannot be banked", region.name()), bank_span));
+
+        } else if bank < first || bank > last {
+            return Err(invalid_section(format!(
+                "Bank {} is outside of the {} banks ({}-{})",
+                bank, region.name(), first, last
+
+            ), bank_span));
+        }
+    }
+
+    if let Some(previous) = sections.iter().find(|s| s.name == name) {
+        return Err(Diagnostic::error(
+            ErrorCode::SectionRedefinition,
+            format!("Section \"{}\" is already defined", name),
+            span
+
+        ).with_label(previous.span, "previously defined here"));
+    }
+
+    symbols.set_builtin("__SECTION__", Expression::String(name.clone(), Span::default()));
+    sections.push(Section::new(name, region, address.map(|a| a.0), bank.map(|b| b.0), span));
+    Ok(sections.len() - 1)
+
+}
+
+fn parse_binary<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    include: &mut dyn Include,
+    section: &mut Section,
+    span: Span
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    let (path, arguments) = parse_path(lexer, symbols, "INCBIN", span)?;
+    let bytes = include.include_binary(&path, span)?;
+
+    // An optional offset and length select a part of the file
+    let mut range = vec![];
+    for (token, token_span) in arguments {
+        match token {
+            Token::Expression(expr) => match evaluate_number(&expr, symbols)? {
+                value if value >= 0 => range.push((value as usize, token_span)),
+                value => return Err(Diagnostic::error(
+                    ErrorCode::ExpectedValue,
+                    format!("Invalid negative value {} for INCBIN directive", value),
+                    token_span
+                ))
+            },
+            Token::Comma => {},
+            token => return Err(unexpected_token(token, token_span))
+        }
+    }
+
+    let start = range.first().map_or(0, |r| r.0);
+    let end = range.get(1).map_or(bytes.len(), |r| start + r.0);
+    if range.len() > 2 {
+        return Err(Diagnostic::error(
+            ErrorCode::UnexpectedToken,
+            "INCBIN only takes a offset and a length after the file path".to_string(),
+            range[2].1
+        ));
+
+    } else if start > bytes.len() || end > bytes.len() {
+        return Err(Diagnostic::error(
+            ErrorCode::ExpectedValue,
+            format!("The selected range {}-{} is outside of \"{}\" ({} bytes)", start, end, path, bytes.len()),
+            range.last().map_or(span, |r| r.1)
+        ));
+    }
+
+    section.add(EntryKind::Bytes(bytes[start..end].to_vec()), span);
+    Ok(())
+
+}
+
+fn parse_space<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    section: &mut Section,
+    span: Span
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    let operands = read_operands(lexer)?;
+    let (size, fill) = match &operands[..] {
+        [(Token::Expression(ref size), _)] => (size, None),
+        [(Token::Expression(ref size), _), (Token::Comma, _), (Token::Expression(ref fill), _)] => (size, Some(fill)),
+        _ => return Err(Diagnostic::error(
+            ErrorCode::ExpectedValue,
+            "Expected a size after DS directive".to_string(),
+            span
+        ))
+    };
+
+    let size = match evaluate_number(size, symbols)? {
+        size if size < 0 => return Err(Diagnostic::error(
+            ErrorCode::ExpectedValue,
+            format!("Invalid negative size {} for DS directive", size),
+            span
+        )),
+        size if size as usize > section.region.size() => return Err(Diagnostic::error(
+            ErrorCode::SectionOverflow,
+            format!(
+                "Invalid size {} for DS directive, a {} section can hold at most {} bytes",
+                size, section.region.name(), section.region.size()
+            ),
+            span
+
+        ).with_label(section.span, "section defined here")),
+        size => size as usize
+    };
+
+    // RAM is not part of the ROM image and can therefore not be filled
+    let fill = match fill {
+        Some(fill) if !section.region.is_rom() => return Err(Diagnostic::error(
+            ErrorCode::DataInRamSection,
+            format!("Reserved space in {} sections cannot have a fill value", section.region.name()),
+            fill.span()
+
+        ).with_label(section.span, "section defined here")),
+        Some(fill) => match evaluate_number(fill, symbols)? {
+            value if !(0..=255).contains(&value) => return Err(Diagnostic::error(
+                ErrorCode::OperandOutOfRange,
+                format!("Invalid fill value {} for DS directive, must be between 0 and 255", value),
+                fill.span()
+            )),
+            value => Some(value as u8)
+        },
+        None => None
+    };
+
+    section.add(EntryKind::Space(size, fill), span);
+    Ok(())
+
+}
+
+fn parse_macro<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    diagnostics: &mut Diagnostics,
+    name: String,
+    span: Span
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    // The parameters are listed in parenthesis after the name, errors
+    // within them are only returned once the body was read
+    let mut params: Vec<String> = vec![];
+    let mut signature = match lexer.peek() {
+        Some(&(Token::LParen, _)) => Ok(()),
+        _ => Err(Diagnostic::error(
+            ErrorCode::ExpectedMacroName,
+            format!("Expected \"(\" after the name of macro \"{}\"", name),
+            span
+        ))
+    };
+
+    while signature.is_ok() {
+        match lexer.next() {
+            Some((Token::RParen, _)) | Some((Token::Eof, _)) | None => break,
+            Some((Token::LParen, _)) | Some((Token::Comma, _)) => {},
+            Some((Token::MacroArg(param), param_span)) => {
+                if params.contains(&param) {
+                    signature = Err(Diagnostic::error(
+                        ErrorCode::MacroArguments,
+                        format!("Duplicate parameter @{} in macro \"{}\"", param, name),
+                        param_span
+                    ));
+                }
+                params.push(param);
+            },
+            Some((Token::Error(diagnostic), _)) => signature = Err(diagnostic),
+            Some((token, token_span)) => signature = Err(unexpected_token(token, token_span))
+        }
+    }
+
+    // Names used within the body count as references even if the macro is
+    // never invoked
+    let mut body = vec![];
+    loop {
+
+        if let Some(&(Token::Eof, end)) = lexer.peek() {
+            return Err(Diagnostic::error(
+                ErrorCode::UnclosedMacro,
+                format!("Macro \"{}\" is missing its ENDMACRO directive", name),
+                span
+
+            ).with_label(end, "file ends here"));
+        }
+
+        match lexer.next() {
+            Some((Token::MacroEnd, _)) | None => break,
+            Some((Token::Error(diagnostic), _)) => diagnostics.report(diagnostic),
+            Some((Token::Macro(_), token_span)) => diagnostics.report(Diagnostic::error(
+                ErrorCode::NestedMacroArgs,
+                format!("Macros cannot be defined within the body of macro \"{}\"", name),
+                token_span
+            )),
+            Some((token, token_span)) => {
+
+                // All macro arguments have to refer to a parameter
+                let mut unknown = vec![];
+                match token {
+                    Token::Expression(ref expr) => expr.visit_names(&mut |n| {
+                        if let Some(param) = n.strip_prefix('@') {
+                            unknown.push(param.to_string());
+                        }
+                    }),
+                    Token::MacroArg(ref param) => unknown.push(param.clone()),
+                    _ => {}
+                }
+
+                for param in unknown.into_iter().filter(|p| !params.contains(p)) {
+                    diagnostics.report(Diagnostic::error(
+                        ErrorCode::UnexpectedMacroArg,
+                        format!("Unknown argument @{} in the body of macro \"{}\"", param, name),
+                        token_span
+
+                    ).with_label(span, "macro defined here"));
+                }
+
+                reference_operands(symbols, &[(token.clone(), token_span)]);
+                body.push((token, token_span));
+
+            }
+        }
+
+    }
+
+    signature?;
+    symbols.define_macro(&name, Macro {
+        params,
+        body
+    }, span)
+
+}
+
+/// Splits the operands of a macro invocation into its argument values
+fn parse_macro_args(operands: Vec<(Token, Span)>) -> Result<Vec<Expression>, Diagnostic> {
+
+    let mut args = vec![];
+    let mut comma: Option<Span> = None;
+    let mut expect_value = true;
+    for (token, span) in operands {
+        let value = match token {
+            Token::Comma if expect_value => {
+                return Err(Diagnostic::error(ErrorCode::MissingOperand, "Expected a argument before \",\"".to_string(), span));
+            },
+            Token::Comma => {
+                comma = Some(span);
+                expect_value = true;
+                continue;
+            },
+            _ if !expect_value => {
+                return Err(Diagnostic::error(ErrorCode::MacroArguments, "Expected \",\" between arguments".to_string(), span));
+            },
+            Token::Expression(Expression::Invalid(diagnostic)) => return Err(diagnostic),
+            Token::Expression(expr) => expr,
+            Token::Number(value) => Expression::Number(value, span),
+            Token::String(value) => Expression::String(value, span),
+            Token::Name(name) | Token::LocalLabelRef(name) => Expression::Name(name, span),
+            token => return Err(unexpected_token(token, span))
+        };
+        args.push(value);
+        expect_value = false;
+    }
+
+    match comma {
+        Some(span) if expect_value => {
+            Err(Diagnostic::error(ErrorCode::MissingOperand, "Expected a argument after \",\"".to_string(), span))
+        },
+        _ => Ok(args)
+    }
+
+}
+
+fn parse_export<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    for (token, span) in read_operands(lexer)? {
+        match token {
+            Token::Expression(Expression::Name(name, _)) |
+            Token::Name(name) | Token::LocalLabelRef(name) => symbols.export(&name),
+            Token::Comma => {},
+            token => return Err(unexpected_token(token, span))
+        }
+    }
+
+    Ok(())
+
+}
+
+fn parse_no_warn<I>(
+    lexer: &mut iter::Peekable<I>,
+    diagnostics: &mut Diagnostics,
+    span: Span
+
+) -> Result<(), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    let mut warnings = vec![];
+    for (token, span) in read_operands(lexer)? {
+        match token {
+            Token::Expression(Expression::Name(name, _)) | Token::Name(name) => match Warning::from_name(&name) {
+                Some(warning) => warnings.push(warning),
+                None => return Err(Diagnostic::error(
+                    ErrorCode::UnknownWarning,
+                    format!("Unknown warning \"{}\"", name),
+                    span
+                ))
+            },
+            Token::Comma => {},
+            token => return Err(unexpected_token(token, span))
+        }
+    }
+
+    // Without any names all warnings are suppressed
+    if warnings.is_empty() {
+        warnings.extend_from_slice(Warning::all());
+    }
+
+    diagnostics.start_suppression(warnings, span);
+    Ok(())
+
+}
+
+/// Parses the string path of a INCLUDE or INCBIN directive, returning the
+/// remaining operands
+fn parse_path<I>(
+    lexer: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable,
+    directive: &str,
+    span: Span
+
+) -> Result<(String, Vec<(Token, Span)>), Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    let mut operands = read_operands(lexer)?.into_iter();
+    match operands.next() {
+        Some((Token::Expression(expr), path_span)) => match expr.evaluate(&mut |n| symbols.resolve(n)) {
+            Expression::String(path, _) => Ok((path, operands.collect())),
+            Expression::Invalid(diagnostic) => Err(diagnostic),
+            _ => Err(Diagnostic::error(
+                ErrorCode::ExpectedString,
+                format!("Expected a file path string after {} directive", directive),
+                path_span
+            ))
+        },
+        _ => Err(Diagnostic::error(
+            ErrorCode::ExpectedString,
+            format!("Expected a file path string after {} directive", directive),
+            span
+        ))
+    }
+
+}
+
+/// Returns the section which code is currently emitted into
+fn current_section<'s>(
+    sections: &'s mut [Section],
+    index: Option<usize>,
+    what: &str,
+    span: Span,
+    needs_rom: bool
+
+) -> Result<&'s mut Section, Diagnostic> {
+    match index {
+        Some(index) => {
+            let section = &mut sections[index];
+            if needs_rom && !section.region.is_rom() {
+                Err(Diagnostic::error(
+                    ErrorCode::DataInRamSection,
+                    format!("{} cannot be placed in {} sections", what, section.region.name()),
+                    span
+
+                ).with_label(section.span, "section defined here"))
+
+            } else {
+                Ok(section)
             }
+        },
+        None => Err(Diagnostic::error(
+            ErrorCode::CodeOutsideSection,
+            format!("{} must be placed inside a section", what),
+            span
+
+        ).with_note("use a SECTION directive before this line".to_string()))
+    }
+}
+
+/// Returns whether a constant whose value failed to evaluate with the error
+/// might refer to names from other object files
+fn is_deferred(code: ErrorCode) -> bool {
+    code == ErrorCode::UndefinedName || code == ErrorCode::UnresolvedLabel
+}
+
+/// Resolves names whose value is already known, labels and names which are
+/// only defined later on are qualified and kept for the linker
+fn resolve_static(symbols: &mut SymbolTable, name: &str) -> Option<Expression> {
+    match symbols.resolve(name) {
+        Some(Expression::Invalid(_)) | None => Some(Expression::Name(symbols.qualify(name), Span::default())),
+        value => value
+    }
+}
+
+/// Parses a optional "[value]" as used for section addresses and banks
+fn parse_bracket_value<I>(
+    tokens: &mut iter::Peekable<I>,
+    symbols: &mut SymbolTable
+
+) -> Result<Option<(usize, Span)>, Diagnostic> where I: Iterator<Item = (Token, Span)> {
+
+    if !matches!(tokens.peek(), Some(&(Token::LBrace, _))) {
+        return Ok(None);
+    }
+
+    let (_, open) = tokens.next().unwrap();
+    let value = match tokens.next() {
+        Some((Token::Expression(expr), span)) => match evaluate_number(&expr, symbols)? {
+            value if value >= 0 => (value as usize, span),
+            _ => return Err(invalid_section("Expected a positive number".to_string(), span))
+        },
+        _ => return Err(invalid_section("Expected a number after \"[\"".to_string(), open))
+    };
+
+    match tokens.next() {
+        Some((Token::RBrace, _)) => Ok(Some(value)),
+        _ => Err(invalid_section("Expected \"]\" after the number".to_string(), value.1))
+    }
+
+}
+
+/// Evaluates a expression which has to result in a number while parsing
+fn evaluate_number(expr: &Expression, symbols: &mut SymbolTable) -> Result<i32, Diagnostic> {
+    match expr.evaluate(&mut |n| symbols.resolve(n)) {
+        Expression::Number(value, _) => Ok(value as i32),
+        Expression::Invalid(diagnostic) => Err(diagnostic),
+        value => Err(Diagnostic::error(
+            ErrorCode::ExpectedValue,
+            "Expected a number".to_string(),
+            value.span()
+        ))
+    }
+}
+
+fn invalid_section(message: String, span: Span) -> Diagnostic {
+    Diagnostic::error(ErrorCode::InvalidSection, message, span)
+}
+
+/// Counts all names within the operands as referenced
+fn reference_operands(symbols: &mut SymbolTable, operands: &[(Token, Span)]) {
+    for (token, _) in operands {
+        match *token {
+            Token::Expression(ref expr) => expr.visit_names(&mut |n| symbols.reference(n)),
+            Token::Name(ref name) | Token::LocalLabelRef(ref name) => symbols.reference(name),
+            _ => {}
         }
+    }
+}
 
+/// Collects all tokens until the end of the current line
+fn read_operands<I>(lexer: &mut iter::Peekable<I>) -> Result<Vec<(Token, Span)>, Diagnostic> where I: Iterator<Item = (Token, Span)> {
+    let mut operands = vec![];
+    while !matches!(lexer.peek(), Some(&(Token::Newline, _)) | Some(&(Token::Eof, _)) | None) {
+        match lexer.next() {
+            Some((Token::Error(diagnostic), _)) => return Err(diagnostic),
+            Some(operand) => operands.push(operand),
+            None => break
+        }
     }
+    Ok(operands)
+}
+
+fn unexpected_token(token: Token, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        ErrorCode::UnexpectedToken,
+        format!("Unexpected {:?}", token.to_type()),
+        span
+    )
+}
 
+fn is_definition(directive: &str) -> bool {
+    matches!(directive, "EQU" | "EQUS" | "SET" | "=")
+}
+
+fn is_condition(name: &str) -> bool {
+    matches!(&name.to_lowercase()[..], "z" | "nz" | "c" | "nc")
+}
+
+/// Skips all remaining tokens of the current line
+fn skip_line<I>(lexer: &mut iter::Peekable<I>) where I: Iterator<Item = (Token, Span)> {
+    while !matches!(lexer.peek(), Some(&(Token::Newline, _)) | Some(&(Token::Eof, _)) | None) {
+        lexer.next();
+    }
 }
 
 impl <'a>SourceIter for SourceFile<'a> {
@@ -75,8 +1374,13 @@ impl <'a>SourceIter for SourceFile<'a> {
     }
 
     fn next(&mut self) -> u8 {
+        self.position.advance(self.last);
         self.last = match self.bytes.next() {
-            Some(o) => o.unwrap_or(0),
+            Some(o) => {
+                let byte = o.unwrap_or(0);
+                self.source.push(byte);
+                byte
+            },
             None => {
                 self.empty = true;
                 0
@@ -105,5 +1409,9 @@ impl <'a>SourceIter for SourceFile<'a> {
         self.empty
     }
 
+    fn span(&self) -> Span {
+        self.position.to_span(self.id)
+    }
+
 }
 
@@ -1,8 +1,51 @@
+use parser::Span;
+
 /// A trait for bytewise iteration through assembly sources
 pub trait SourceIter {
     fn get(&self) -> u8;
     fn next(&mut self) -> u8;
     fn peek(&mut self) -> u8;
     fn is_empty(&self) -> bool;
+
+    /// Returns a zero length span at the position of the current byte
+    fn span(&self) -> Span;
 }
 
+/// Tracks the position of the current byte while iterating through a source
+#[derive(Default)]
+pub struct SourcePosition {
+    offset: usize,
+    line: usize,
+    column: usize
+}
+
+impl SourcePosition {
+
+    /// Moves the position past the previous byte
+    pub fn advance(&mut self, previous: u8) {
+        if self.line == 0 {
+            self.line = 1;
+            self.column = 1;
+
+        } else if previous == b'\n' {
+            self.offset += 1;
+            self.line += 1;
+            self.column = 1;
+
+        } else {
+            self.offset += 1;
+            self.column += 1;
+        }
+    }
+
+    pub fn to_span(&self, file: usize) -> Span {
+        Span {
+            file,
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            column: self.column
+        }
+    }
+
+}
@@ -1,48 +1,35 @@
-use compiler::SourceIter;
+use compiler::{SourceIter, SourcePosition};
 use std::iter;
 use std::str;
 
-use parser::Lexer;
-use parser::Token;
+use parser::Span;
 
 /// A struct which represents an assembly source from a string
 pub struct SourceString<'a> {
+
+    /// A ID used to identify the source in spans
+    pub id: usize,
+
     path: &'a str,
     bytes: iter::Peekable<str::Bytes<'a>>,
     last: u8,
-    empty: bool
+    empty: bool,
+    position: SourcePosition
 }
 
 impl <'a>SourceString<'a> {
 
     pub fn new(path: &'a str, source: &'a str) -> SourceString<'a> {
         SourceString {
+            id: 0,
             path: path,
             bytes: source.bytes().peekable(),
             last: 0,
-            empty: false
+            empty: false,
+            position: SourcePosition::default()
         }
     }
 
-    pub fn parse(&mut self) {
-
-        let mut lexer = Lexer::new(self).peekable();
-
-        loop {
-            match lexer.next().unwrap() {
-                Token::Eof => {
-                    break;
-                },
-                Token::Error(ref err) => {
-                    println!("Error: {}", err);
-                    break;
-                },
-                token => println!("{:?}", token)
-            }
-        }
-
-    }
-
 }
 
 impl <'a>SourceIter for SourceString<'a> {
@@ -52,6 +39,7 @@ impl <'a>SourceIter for SourceString<'a> {
     }
 
     fn next(&mut self) -> u8 {
+        self.position.advance(self.last);
         self.last = match self.bytes.next() {
             Some(o) => o,
             None => {
@@ -76,5 +64,9 @@ impl <'a>SourceIter for SourceString<'a> {
         self.empty
     }
 
+    fn span(&self) -> Span {
+        self.position.to_span(self.id)
+    }
+
 }
 
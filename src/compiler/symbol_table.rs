@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+
+use compiler::{Diagnostic, ErrorCode};
+use parser::{Expression, Span, Token};
+
+/// Names of the symbols which are provided by the assembler itself
+const BUILTINS: [&str; 7] = [
+    "__FILE__",
+    "__LINE__",
+    "__SECTION__",
+    "__ADDRESS__",
+    "__VERSION__",
+    "__DATE__",
+    "__TIME__"
+];
+
+/// The different kinds of user defined symbols
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum SymbolKind {
+    /// Defined once via EQU, EQUS or from the command line
+    Constant,
+    /// Defined via SET or =, can be redefined later on
+    Variable,
+    /// A address within the code, its value is only known after linking
+    Label,
+    /// Defined via MACRO, can only be invoked but not used as a value
+    Macro
+}
+
+impl SymbolKind {
+
+    /// Returns a human readable name of the kind
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SymbolKind::Constant => "constant",
+            SymbolKind::Variable => "variable",
+            SymbolKind::Label => "label",
+            SymbolKind::Macro => "macro"
+        }
+    }
+
+}
+
+/// A named value which can be referenced from within expressions
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub value: Expression,
+
+    /// Where the symbol was defined, empty for command line definitions
+    pub span: Span
+}
+
+/// The parameters and body of a macro which are expanded on every invocation
+#[derive(Debug, PartialEq, Clone)]
+pub struct Macro {
+
+    /// The names of the parameters without their leading "@"
+    pub params: Vec<String>,
+
+    pub body: Vec<(Token, Span)>
+}
+
+/// A table of all named values which can be referenced from within expressions
+#[derive(Default)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+    builtins: HashMap<&'static str, Expression>,
+    macros: HashMap<String, Macro>,
+
+    /// How often each name was referenced, including names which are not
+    /// defined yet
+    references: HashMap<String, usize>,
+
+    /// Names which are used from outside of the sources and therefore never
+    /// count as unused
+    exports: HashSet<String>,
+
+    /// The global label to which local label names belong
+    scope: Option<String>,
+
+    /// The names under which shadowed local labels were defined again, so
+    /// earlier references keep referring to the earlier definition
+    shadows: HashMap<String, String>
+}
+
+impl SymbolTable {
+
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: HashMap::new(),
+            builtins: HashMap::new(),
+            macros: HashMap::new(),
+            references: HashMap::new(),
+            exports: HashSet::new(),
+            scope: None,
+            shadows: HashMap::new()
+        }
+    }
+
+    /// Defines a new symbol, only variables can be redefined once they exist
+    /// and only as variables again
+    pub fn define(&mut self, name: &str, kind: SymbolKind, value: Expression, span: Span) -> Result<(), Diagnostic> {
+
+        if BUILTINS.contains(&name) {
+            return Err(Diagnostic::error(
+                ErrorCode::BuiltinRedefinition,
+                format!("Cannot redefine built-in symbol \"{}\"", name),
+                span
+            ));
+        }
+
+        let error = match self.symbols.get(name) {
+            Some(symbol) if symbol.kind == SymbolKind::Constant => Some((
+                symbol,
+                ErrorCode::ConstantRedefinition,
+                format!("Constant \"{}\" is already defined", name)
+            )),
+            Some(symbol) if symbol.kind == SymbolKind::Label => Some((
+                symbol,
+                ErrorCode::LabelRedefinition,
+                format!("Label \"{}\" is already defined", name)
+            )),
+            Some(symbol) if symbol.kind == SymbolKind::Macro => Some((
+                symbol,
+                ErrorCode::MacroRedefinition,
+                format!("Macro \"{}\" is already defined", name)
+            )),
+            Some(symbol) if kind != SymbolKind::Variable => Some((
+                symbol,
+                ErrorCode::VariableRedefinition,
+                format!("Cannot redefine variable \"{}\" as a {}", name, kind.name())
+            )),
+            _ => None
+        };
+
+        if let Some((symbol, code, message)) = error {
+            let diagnostic = Diagnostic::error(code, message, span);
+            return Err(if symbol.span == Span::default() {
+                diagnostic.with_note(format!("\"{}\" was defined on the command line", name))
+
+            } else {
+                diagnostic.with_label(symbol.span, "previously defined here")
+            });
+        }
+
+        self.symbols.insert(name.to_string(), Symbol {
+            kind,
+            value,
+            span
+        });
+
+        Ok(())
+
+    }
+
+    /// Defines a constant whose value refers to labels or names which are not
+    /// defined yet, the value is only evaluated once it is used
+    pub fn define_deferred(&mut self, name: &str, value: &Expression, span: Span) -> Result<(), Diagnostic> {
+
+        let value = self.substitute(value);
+
+        let mut recursive = false;
+        value.visit_names(&mut |n| recursive |= n == name);
+        if recursive {
+            return Err(Diagnostic::error(
+                ErrorCode::UndefinedName,
+                format!("Constant \"{}\" refers to itself", name),
+                span
+            ));
+        }
+
+        self.define(name, SymbolKind::Constant, value, span)
+
+    }
+
+    /// Defines a new macro, its name must not be used by any other symbol
+    pub fn define_macro(&mut self, name: &str, definition: Macro, span: Span) -> Result<(), Diagnostic> {
+
+        let value = Expression::Invalid(Diagnostic::error(
+            ErrorCode::MacroAsValue,
+            format!("Macro \"{}\" cannot be used as a value", name),
+            span
+        ));
+
+        self.define(name, SymbolKind::Macro, value, span)?;
+        self.macros.insert(name.to_string(), definition);
+        Ok(())
+
+    }
+
+    /// Returns the definition of the macro with the given name
+    pub fn get_macro(&self, name: &str) -> Option<&Macro> {
+        self.macros.get(name)
+    }
+
+    /// Returns a new name under which a shadowed local label is defined
+    /// again, all following references to the label resolve to the new name
+    pub fn shadow(&mut self, name: &str) -> String {
+        let mut count = 2;
+        while self.symbols.contains_key(&format!("{}#{}", name, count)) {
+            count += 1;
+        }
+        let shadow = format!("{}#{}", name, count);
+        self.shadows.insert(name.to_string(), shadow.clone());
+        shadow
+    }
+
+    /// Returns the symbol with the given name, built-ins are not included
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+    /// Sets the global label which local label names are resolved against
+    pub fn set_scope(&mut self, scope: Option<String>) {
+        self.scope = scope;
+    }
+
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_ref().map(|s| &s[..])
+    }
+
+    /// Prefixes local label names with the name of the current scope
+    pub fn qualify(&self, name: &str) -> String {
+        match self.scope {
+            Some(ref scope) if name.starts_with('.') => {
+                let name = format!("{}{}", scope, name);
+                self.shadows.get(&name).cloned().unwrap_or(name)
+            },
+            _ => name.to_string()
+        }
+    }
+
+    /// Records a reference to a name which might only be defined later on
+    pub fn reference(&mut self, name: &str) {
+        *self.references.entry(self.qualify(name)).or_insert(0) += 1;
+    }
+
+    /// Marks a name as exported, which might only be defined later on
+    pub fn export(&mut self, name: &str) {
+        let name = self.qualify(name);
+        self.exports.insert(name);
+    }
+
+    pub fn exports(&self) -> Vec<&String> {
+        self.exports.iter().collect()
+    }
+
+    pub fn is_exported(&self, name: &str) -> bool {
+        self.exports.contains(name)
+    }
+
+    /// Returns all user defined symbols which were neither referenced nor
+    /// exported
+    pub fn unused(&self) -> Vec<(&String, &Symbol)> {
+        self.symbols.iter().filter(|&(name, _)| {
+            !self.references.contains_key(name) && !self.exports.contains(name)
+
+        }).collect()
+    }
+
+    /// Returns all user defined symbols
+    pub fn symbols(&self) -> Vec<(&String, &Symbol)> {
+        self.symbols.iter().collect()
+    }
+
+    /// Replaces all names within the expression with the values of the
+    /// constants and variables they refer to, labels and undefined names are
+    /// kept
+    pub fn substitute(&mut self, expr: &Expression) -> Expression {
+        expr.substitute(&mut |n| match self.resolve(n) {
+            Some(Expression::Invalid(_)) | None => Some(Expression::Name(self.qualify(n), Span::default())),
+            Some(value) => Some(self.substitute(&value))
+        })
+    }
+
+    /// Replaces the placeholder value of a label with its linked address
+    pub fn set_address(&mut self, name: &str, address: usize) {
+        if let Some(symbol) = self.symbols.get_mut(name) {
+            symbol.value = Expression::Number(address as f32, symbol.span);
+        }
+    }
+
+    /// Sets the current value of a built-in symbol
+    pub fn set_builtin(&mut self, name: &'static str, value: Expression) {
+        debug_assert!(BUILTINS.contains(&name));
+        self.builtins.insert(name, value);
+    }
+
+    /// Removes the value of a built-in symbol which is not available in the
+    /// current context
+    pub fn unset_builtin(&mut self, name: &'static str) {
+        self.builtins.remove(name);
+    }
+
+    /// Returns the value of the symbol with the given name and counts it as
+    /// a reference
+    pub fn resolve(&mut self, name: &str) -> Option<Expression> {
+        match self.builtins.get(name) {
+            Some(value) => Some(value.clone()),
+            None => {
+                self.reference(name);
+                self.symbols.get(&self.qualify(name)).map(|s| s.value.clone())
+            }
+        }
+    }
+
+}
+
+/// Returns the name of a label as written in the source, without the suffix
+/// of shadowed local labels
+pub fn label_name(name: &str) -> &str {
+    name.split('#').next().unwrap_or(name)
+}
+
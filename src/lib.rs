@@ -1,3 +1,6 @@
+#[macro_use]
+extern crate clap;
+
 pub use compiler::*;
 pub use parser::*;
 
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use compiler::SymbolKind;
+use linker::ObjectFile;
+use linker::object::{Reader, Writer};
+
+/// Identifies gbasm archives
+const MAGIC: &[u8; 4] = b"GBAR";
+
+/// The version of the archive format, increased with every incompatible change
+const ARCHIVE_VERSION: u8 = 1;
+
+/// A object file stored within a archive
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArchiveMember {
+    pub name: String,
+    pub bytes: Vec<u8>
+}
+
+/// A static library of object files with a index of the symbols they
+/// define, so only the members which are actually needed get linked
+#[derive(Debug, PartialEq, Clone)]
+pub struct Archive {
+    pub members: Vec<ArchiveMember>,
+    index: HashMap<String, usize>
+}
+
+impl Archive {
+
+    /// Bundles the object files and indexes their symbols, symbols defined by
+    /// multiple members are looked up in the first one
+    pub fn new(members: Vec<ArchiveMember>) -> Result<Archive, String> {
+
+        let mut index = HashMap::new();
+        for (i, member) in members.iter().enumerate() {
+            let object = ObjectFile::from_bytes(&member.bytes, 0).map_err(|err| {
+                format!("Invalid archive member \"{}\": {}", member.name, err)
+            })?;
+            for (name, symbol) in object.symbols {
+                if symbol.kind != SymbolKind::Macro {
+                    index.entry(name).or_insert(i);
+                }
+            }
+        }
+
+        Ok(Archive {
+            members,
+            index
+        })
+
+    }
+
+    /// Returns the index of the member which defines the symbol
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.index.get(name).cloned()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+
+        let mut w = Writer::new(MAGIC, ARCHIVE_VERSION);
+
+        w.usize(self.members.len());
+        for member in &self.members {
+            w.string(&member.name);
+            w.usize(member.bytes.len());
+            w.bytes.extend_from_slice(&member.bytes);
+        }
+
+        let mut index: Vec<(&String, &usize)> = self.index.iter().collect();
+        index.sort();
+
+        w.usize(index.len());
+        for (name, member) in index {
+            w.string(name);
+            w.usize(*member);
+        }
+
+        w.bytes
+
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Archive, String> {
+
+        let mut r = Reader::new(bytes, MAGIC, ARCHIVE_VERSION, "archive")?;
+
+        let mut members = vec![];
+        for _ in 0..r.usize()? {
+            let name = r.string()?;
+            let length = r.usize()?;
+            members.push(ArchiveMember {
+                name,
+                bytes: r.take(length)?.to_vec()
+            });
+        }
+
+        let mut index = HashMap::new();
+        for _ in 0..r.usize()? {
+            let name = r.string()?;
+            match r.usize()? {
+                member if member < members.len() => index.insert(name, member),
+                _ => return Err("Invalid member in archive index".to_string())
+            };
+        }
+
+        r.finish()?;
+        Ok(Archive {
+            members,
+            index
+        })
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use compiler::{Symbol, SymbolKind};
+    use linker::ObjectFile;
+    use parser::{Expression, Span};
+    use super::{Archive, ArchiveMember};
+
+    fn member(name: &str, symbols: &[(&str, SymbolKind)]) -> ArchiveMember {
+        let object = ObjectFile {
+            sources: vec![],
+            sections: vec![],
+            symbols: symbols.iter().map(|&(name, kind)| (name.to_string(), Symbol {
+                kind,
+                value: Expression::Number(1.0, Span::default()),
+                span: Span::default()
+
+            })).collect(),
+            exports: vec![]
+        };
+        ArchiveMember {
+            name: name.to_string(),
+            bytes: object.to_bytes()
+        }
+    }
+
+    fn archive() -> Archive {
+        Archive::new(vec![
+            member("sound.o", &[("sound_init", SymbolKind::Label), ("SOUND_CHANNELS", SymbolKind::Constant)]),
+            member("text.o", &[("text_init", SymbolKind::Label), ("sound_init", SymbolKind::Label), ("print", SymbolKind::Macro)])
+
+        ]).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let archive = archive();
+        assert_eq!(Archive::from_bytes(&archive.to_bytes()), Ok(archive));
+    }
+
+    #[test]
+    fn test_index() {
+        let archive = Archive::from_bytes(&archive().to_bytes()).unwrap();
+        assert_eq!(archive.find("SOUND_CHANNELS"), Some(0));
+        assert_eq!(archive.find("text_init"), Some(1));
+
+        // The first member which defines a name is used
+        assert_eq!(archive.find("sound_init"), Some(0));
+
+        // Macros cannot be linked
+        assert_eq!(archive.find("print"), None);
+        assert_eq!(archive.find("missing"), None);
+    }
+
+    #[test]
+    fn test_invalid_member() {
+        let result = Archive::new(vec![ArchiveMember {
+            name: "broken.o".to_string(),
+            bytes: b"GBOB".to_vec()
+        }]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let bytes = archive().to_bytes();
+        assert!(Archive::from_bytes(b"GBOB\x01").is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Archive::from_bytes(&trailing).is_err());
+
+        for length in 0..bytes.len() {
+            assert!(Archive::from_bytes(&bytes[..length]).is_err(), "accepted {} of {} bytes", length, bytes.len());
+        }
+    }
+
+}
@@ -1,24 +1,347 @@
-use compiler::SourceFile;
+use compiler::{Diagnostic, Diagnostics, EntryKind, ErrorCode, Expansion, Region, Section, SymbolTable, Warning, Width};
+use parser::{Expression, Span};
+
+/// The size of a single ROM bank in bytes
+const BANK_SIZE: usize = 0x4000;
 
 pub struct Linker;
 
 impl Linker {
 
-    pub fn optimize(allow_unsafe: bool) {
-        println!("Optimizing instructions (unsafe={})", allow_unsafe);
+    pub fn optimize(_allow_unsafe: bool) {
     }
 
-    pub fn init_files(files: &mut Vec<SourceFile>) {
-        for f in files.iter_mut() {
-            f.id = 0;
+    /// Places all sections into their regions, assigns the addresses of all
+    /// labels and encodes the contents of all ROM sections
+    ///
+    /// Bytes of the base image which are not padding are treated as occupied
+    /// when placing floating ROM sections.
+    pub fn link(sections: &mut [Section], base: &[u8], symbols: &mut SymbolTable, diagnostics: &mut Diagnostics) {
+
+        let placed = place_sections(sections, base, diagnostics);
+
+        for section in sections.iter() {
+            for (name, address, _) in section.labels() {
+                symbols.set_address(name, address);
+            }
         }
+
+        // Sections which could not be placed have already been reported and
+        // are not encoded since their addresses are unknown
+        for (section, _) in sections.iter_mut().zip(placed).filter(|&(ref s, placed)| placed && s.region.is_rom()) {
+            encode_section(section, symbols, diagnostics);
+        }
+
     }
 
-    pub fn link_files(files: &mut Vec<SourceFile>) {
-        for f in files.iter_mut() {
-            f.id = 1;
+    /// Returns the number of 16KB banks of the ROM image
+    pub fn rom_banks(sections: &[Section]) -> usize {
+        let banks = sections.iter().filter(|s| s.region == Region::RomX).map(|s| s.bank + 1).max().unwrap_or(2);
+        banks.max(2).next_power_of_two()
+    }
+
+    /// Combines all linked ROM sections into a image with valid header and
+    /// global checksums
+    ///
+    /// The image is at least 32KB in size and grows in powers of two with the
+    /// highest used bank.
+    pub fn rom_image(sections: &[Section]) -> Vec<u8> {
+        Linker::patch_image(&[], sections)
+    }
+
+    /// Assembles all linked ROM sections into a copy of the base image, which
+    /// is extended when the sections use banks beyond its end
+    pub fn patch_image(base: &[u8], sections: &[Section]) -> Vec<u8> {
+
+        let mut rom = base.to_vec();
+        let size = rom.len().max(Linker::rom_banks(sections) * BANK_SIZE);
+        rom.resize(size, 0);
+
+        for section in sections.iter().filter(|s| s.region.is_rom()) {
+            let offset = rom_offset(section);
+            rom[offset..offset + section.data.len()].copy_from_slice(&section.data);
         }
+
+        // Header checksum over the title, licensee and cartridge information
+        rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |c, b| c.wrapping_sub(*b).wrapping_sub(1));
+
+        // Global checksum over all bytes except for the checksum itself
+        let checksum = rom.iter().enumerate().filter(|&(i, _)| i != 0x14E && i != 0x14F).fold(0u16, |c, (_, b)| {
+            c.wrapping_add(u16::from(*b))
+        });
+        rom[0x14E] = (checksum >> 8) as u8;
+        rom[0x14F] = checksum as u8;
+
+        rom
+
     }
 
 }
 
+
+// Placement ------------------------------------------------------------------
+/// Assigns addresses and banks to all sections, returns for each section
+/// whether it could be placed
+fn place_sections(sections: &mut [Section], base: &[u8], diagnostics: &mut Diagnostics) -> Vec<bool> {
+
+    // Sections with fixed addresses are placed first so floating sections can
+    // fill the remaining gaps, larger sections go before smaller ones
+    let mut order: Vec<usize> = (0..sections.len()).collect();
+    order.sort_by_key(|&i| {
+        let section = &sections[i];
+        (section.fixed_address.is_none(), section.fixed_bank.is_none(), !section.size)
+    });
+
+    // The already occupied address ranges as (section, bank, start, end)
+    let mut used: Vec<(usize, usize, usize, usize)> = vec![];
+    let mut placed = vec![false; sections.len()];
+    for index in order {
+
+        let (region, size) = (sections[index].region, sections[index].size);
+        let banks = match sections[index].fixed_bank {
+            Some(bank) => (bank, bank),
+            None => region.banks()
+        };
+
+        let placement = match sections[index].fixed_address {
+            Some(address) if address + size > region.end() => Err(Diagnostic::error(
+                ErrorCode::SectionOverflow,
+                format!(
+                    "Section \"{}\" ({} bytes) does not fit at ${:0>4X}, it would end after the {} region at ${:0>4X}",
+                    sections[index].name, size, address, region.name(), region.end() - 1
+                ),
+                sections[index].span
+            )),
+            Some(address) => {
+                let overlap = (banks.0..=banks.1).map(|bank| {
+                    (bank, overlapping(sections, &used, region, bank, address, size))
+
+                }).collect::<Vec<_>>();
+
+                match overlap.iter().find(|o| o.1.is_none()) {
+                    Some(&(bank, _)) => Ok((address, bank)),
+                    None => {
+                        let other = &sections[overlap[0].1.unwrap()];
+                        Err(Diagnostic::error(
+                            ErrorCode::SectionOverlap,
+                            format!("Section \"{}\" overlaps with section \"{}\"", sections[index].name, other.name),
+                            sections[index].span
+
+                        ).with_label(other.span, "overlapping section defined here"))
+                    }
+                }
+            },
+            None => {
+                let space = (banks.0..=banks.1).filter_map(|bank| {
+                    find_space(sections, &used, base, region, bank, size).map(|address| (address, bank))
+
+                }).next();
+
+                space.ok_or_else(|| Diagnostic::error(
+                    ErrorCode::SectionOverflow,
+                    format!(
+                        "Not enough space in {} for section \"{}\" ({} bytes)",
+                        region.name(), sections[index].name, size
+                    ),
+                    sections[index].span
+                ))
+            }
+        };
+
+        match placement {
+            Ok((address, bank)) => {
+
+                // Fixed sections may replace code and data of the base image
+                if let Some(offset) = base_usage(base, region, bank, address, size) {
+                    diagnostics.report(Diagnostic::warning(
+                        Warning::Overwrite,
+                        format!(
+                            "Section \"{}\" overwrites bytes of the base ROM up to ${:0>4X}",
+                            sections[index].name, address + offset
+                        ),
+                        sections[index].span
+                    ));
+                }
+
+                sections[index].address = address;
+                sections[index].bank = bank;
+                used.push((index, bank, address, address + size));
+                placed[index] = true;
+            },
+            Err(diagnostic) => diagnostics.report(diagnostic)
+        }
+
+    }
+
+    placed
+
+}
+
+/// Returns the first section which overlaps with the given address range
+fn overlapping(
+    sections: &[Section],
+    used: &[(usize, usize, usize, usize)],
+    region: Region,
+    bank: usize,
+    address: usize,
+    size: usize
+
+) -> Option<usize> {
+    used.iter().find(|&&(index, b, start, end)| {
+        sections[index].region == region && b == bank && address < end && start < address + size
+
+    }).map(|u| u.0)
+}
+
+/// Returns the lowest free address within a bank of the region which can
+/// hold the given number of bytes without replacing bytes of the base image
+fn find_space(
+    sections: &[Section],
+    used: &[(usize, usize, usize, usize)],
+    base: &[u8],
+    region: Region,
+    bank: usize,
+    size: usize
+
+) -> Option<usize> {
+
+    let ranges: Vec<(usize, usize)> = used.iter().filter(|&&(index, b, _, _)| {
+        sections[index].region == region && b == bank
+
+    }).map(|&(_, _, start, end)| (start, end)).collect();
+
+    // Move past overlapping sections and used bytes until the range is free
+    let mut address = region.start();
+    while address + size <= region.end() {
+        if let Some(&(_, end)) = ranges.iter().find(|&&(start, end)| address < end && start < address + size) {
+            address = end;
+
+        } else if let Some(offset) = base_usage(base, region, bank, address, size) {
+            address += offset + 1;
+
+        } else {
+            break;
+        }
+    }
+
+    if address + size <= region.end() {
+        Some(address)
+
+    } else {
+        None
+    }
+
+}
+
+
+// Encoding -------------------------------------------------------------------
+fn encode_section(section: &mut Section, symbols: &mut SymbolTable, diagnostics: &mut Diagnostics) {
+
+    let mut data = Vec::with_capacity(section.size);
+    for entry in &section.entries {
+
+        let address = section.address + entry.offset;
+        let result = match entry.kind {
+            EntryKind::Label(_) => Ok(vec![]),
+            EntryKind::Instruction(ref instruction) => instruction.to_bytes(address, &mut |n| resolve(symbols, n, address)),
+            EntryKind::Bytes(ref bytes) => Ok(bytes.clone()),
+            EntryKind::Value(ref expr, width) => encode_value(expr, width, address, symbols, diagnostics, &entry.expansions),
+            EntryKind::Space(size, fill) => Ok(vec![fill.unwrap_or(0); size])
+        };
+
+        match result {
+            Ok(bytes) => data.extend(bytes),
+            Err(diagnostic) => {
+                // Errors without a location refer to the whole statement
+                let diagnostic = if diagnostic.span == Span::default() {
+                    diagnostic.with_span(entry.span)
+
+                } else {
+                    diagnostic
+                };
+                diagnostics.report(diagnostic.with_expansions(entry.expansions.clone()));
+                data.extend(vec![0; entry.size()]);
+            }
+        }
+
+    }
+
+    section.data = data;
+
+}
+
+fn encode_value(
+    expr: &Expression,
+    width: Width,
+    address: usize,
+    symbols: &mut SymbolTable,
+    diagnostics: &mut Diagnostics,
+    expansions: &[Expansion]
+
+) -> Result<Vec<u8>, Diagnostic> {
+    match expr.evaluate(&mut |n| resolve(symbols, n, address)) {
+        Expression::Number(value, span) => {
+            let (min, max, storage) = match width {
+                Width::Byte => (-128.0, 255.0, "byte"),
+                Width::Word => (-32768.0, 65535.0, "word")
+            };
+            if value < min || value > max {
+                diagnostics.report(Diagnostic::warning(
+                    Warning::Truncation,
+                    format!("Value {} does not fit into a {} and will be truncated", value, storage),
+                    span
+
+                ).with_expansions(expansions.to_vec()));
+            }
+
+            let value = value as i32;
+            Ok(match width {
+                Width::Byte => vec![value as u8],
+                Width::Word => vec![value as u8, (value >> 8) as u8]
+            })
+        },
+        Expression::Invalid(diagnostic) => Err(diagnostic),
+        value => Err(Diagnostic::error(
+            ErrorCode::ExpectedValue,
+            "Expected a number".to_string(),
+            value.span()
+        ))
+    }
+}
+
+
+// Helpers --------------------------------------------------------------------
+/// Resolves a name for the statement at the given address, __ADDRESS__ is
+/// left unresolved by statements in floating sections
+fn resolve(symbols: &mut SymbolTable, name: &str, address: usize) -> Option<Expression> {
+    if name == "__ADDRESS__" {
+        Some(Expression::Number(address as f32, Span::default()))
+
+    } else {
+        symbols.resolve(name)
+    }
+}
+
+/// Returns the offset of the last byte within the given range of a ROM
+/// region which is used by the base image, $00 and $FF are padding
+fn base_usage(base: &[u8], region: Region, bank: usize, address: usize, size: usize) -> Option<usize> {
+    if region.is_rom() {
+        let offset = image_offset(region, bank, address);
+        let (start, end) = (offset.min(base.len()), (offset + size).min(base.len()));
+        base[start..end].iter().rposition(|&b| b != 0x00 && b != 0xFF)
+
+    } else {
+        None
+    }
+}
+
+fn rom_offset(section: &Section) -> usize {
+    image_offset(section.region, section.bank, section.address)
+}
+
+fn image_offset(region: Region, bank: usize, address: usize) -> usize {
+    match region {
+        Region::RomX => bank * BANK_SIZE + address - Region::RomX.start(),
+        _ => address
+    }
+}
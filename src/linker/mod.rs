@@ -1,4 +1,10 @@
+pub use self::archive::{Archive, ArchiveMember};
 pub use self::linker::Linker;
+pub use self::object::{ObjectFile, ObjectSource};
+pub use self::patch::{create_bps, create_ips};
 
+mod archive;
 mod linker;
+mod object;
+mod patch;
 
@@ -0,0 +1,726 @@
+use compiler::{Diagnostic, Entry, EntryKind, ErrorCode, Expansion, Instruction, Operand, Region, Section, Symbol, SymbolKind, Width};
+use parser::{Expression, Operator, Span};
+
+/// Identifies gbasm object files
+const MAGIC: &[u8; 4] = b"GBOB";
+
+/// The version of the object format, increased with every incompatible change
+const OBJECT_VERSION: u8 = 1;
+
+const OPERATORS: [Operator; 27] = [
+    Operator::Paren,
+    Operator::Call,
+    Operator::Conditional,
+    Operator::ConditionalElse,
+    Operator::LogicalOr,
+    Operator::LogicalAnd,
+    Operator::BitwiseOr,
+    Operator::BitwiseXor,
+    Operator::BitwiseAnd,
+    Operator::Equal,
+    Operator::NotEqual,
+    Operator::LessThan,
+    Operator::GreaterThan,
+    Operator::LessThanEqual,
+    Operator::GreaterThanEqual,
+    Operator::ShiftLeft,
+    Operator::ShiftRight,
+    Operator::Plus,
+    Operator::Minus,
+    Operator::Negate,
+    Operator::Multiply,
+    Operator::Divide,
+    Operator::Modulo,
+    Operator::Power,
+    Operator::IntegerDivide,
+    Operator::UnaryNot,
+    Operator::UnaryMinus
+];
+
+/// A source file whose text is stored in a object file so diagnostics
+/// reported while linking can still show the affected lines
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObjectSource {
+    pub path: String,
+    pub filename: String,
+    pub source: Vec<u8>
+}
+
+/// The parsed but unlinked contents of one or more source files
+///
+/// Names within instruction arguments and data values which could not be
+/// resolved while parsing are kept as expressions and act as relocations
+/// which the linker resolves once all label addresses are known.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObjectFile {
+    pub sources: Vec<ObjectSource>,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<(String, Symbol)>,
+    pub exports: Vec<String>
+}
+
+impl ObjectFile {
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+
+        let mut w = Writer::new(MAGIC, OBJECT_VERSION);
+
+        w.usize(self.sources.len());
+        for source in &self.sources {
+            w.string(&source.path);
+            w.string(&source.filename);
+            w.usize(source.source.len());
+            w.bytes.extend_from_slice(&source.source);
+        }
+
+        w.usize(self.sections.len());
+        for section in &self.sections {
+            w.section(section);
+        }
+
+        w.usize(self.symbols.len());
+        for (name, symbol) in &self.symbols {
+            w.string(name);
+            w.symbol(symbol);
+        }
+
+        w.usize(self.exports.len());
+        for name in &self.exports {
+            w.string(name);
+        }
+
+        w.bytes
+
+    }
+
+    /// Reads a object file, the IDs of its source files in all spans are
+    /// offset by the given number
+    pub fn from_bytes(bytes: &[u8], file_offset: usize) -> Result<ObjectFile, String> {
+
+        let mut r = Reader::new(bytes, MAGIC, OBJECT_VERSION, "object file")?;
+        r.file_offset = file_offset;
+
+        let mut sources = vec![];
+        for _ in 0..r.usize()? {
+            let path = r.string()?;
+            let filename = r.string()?;
+            let length = r.usize()?;
+            sources.push(ObjectSource {
+                path,
+                filename,
+                source: r.take(length)?.to_vec()
+            });
+        }
+
+        let mut sections = vec![];
+        for _ in 0..r.usize()? {
+            sections.push(r.section()?);
+        }
+
+        let mut symbols = vec![];
+        for _ in 0..r.usize()? {
+            let name = r.string()?;
+            symbols.push((name, r.symbol()?));
+        }
+
+        let mut exports = vec![];
+        for _ in 0..r.usize()? {
+            exports.push(r.string()?);
+        }
+
+        r.finish()?;
+        Ok(ObjectFile {
+            sources,
+            sections,
+            symbols,
+            exports
+        })
+
+    }
+
+}
+
+
+// Writing --------------------------------------------------------------------
+pub struct Writer {
+    pub bytes: Vec<u8>
+}
+
+impl Writer {
+
+    pub fn new(magic: &[u8], version: u8) -> Writer {
+        let mut bytes = magic.to_vec();
+        bytes.push(version);
+        Writer {
+            bytes
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u32(value as u32);
+    }
+
+    fn optional(&mut self, value: Option<usize>) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                self.usize(value);
+            },
+            None => self.u8(0)
+        }
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.usize(span.file);
+        self.usize(span.start);
+        self.usize(span.end);
+        self.usize(span.line);
+        self.usize(span.column);
+    }
+
+    fn section(&mut self, section: &Section) {
+        self.string(&section.name);
+        self.u8(Region::all().iter().position(|r| *r == section.region).unwrap() as u8);
+        self.optional(section.fixed_address);
+        self.optional(section.fixed_bank);
+        self.span(section.span);
+        self.usize(section.entries.len());
+        for entry in &section.entries {
+            self.entry(entry);
+        }
+    }
+
+    fn entry(&mut self, entry: &Entry) {
+        match entry.kind {
+            EntryKind::Label(ref name) => {
+                self.u8(0);
+                self.string(name);
+            },
+            EntryKind::Instruction(ref instruction) => {
+                self.u8(1);
+                self.string(&instruction.mnemonic);
+                self.usize(instruction.operands.len());
+                for operand in &instruction.operands {
+                    self.operand(operand);
+                }
+            },
+            EntryKind::Bytes(ref bytes) => {
+                self.u8(2);
+                self.usize(bytes.len());
+                self.bytes.extend_from_slice(bytes);
+            },
+            EntryKind::Value(ref expr, width) => {
+                self.u8(3);
+                self.u8(if width == Width::Byte { 1 } else { 2 });
+                self.expression(expr);
+            },
+            EntryKind::Space(size, fill) => {
+                self.u8(4);
+                self.usize(size);
+                self.optional(fill.map(usize::from));
+            }
+        }
+        self.span(entry.span);
+        self.usize(entry.expansions.len());
+        for expansion in &entry.expansions {
+            self.string(&expansion.name);
+            self.span(expansion.span);
+            self.optional(expansion.iteration);
+        }
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        match *operand {
+            Operand::Register(ref name) => {
+                self.u8(0);
+                self.string(name);
+            },
+            Operand::Memory(ref name) => {
+                self.u8(1);
+                self.string(name);
+            },
+            Operand::Address(ref expr) => {
+                self.u8(2);
+                self.expression(expr);
+            },
+            Operand::Value(ref expr) => {
+                self.u8(3);
+                self.expression(expr);
+            },
+            Operand::StackOffset(ref expr) => {
+                self.u8(4);
+                self.expression(expr);
+            },
+            Operand::Offset(offset) => {
+                self.u8(5);
+                self.u32(offset as u32);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match *expr {
+            Expression::Number(value, span) => {
+                self.u8(0);
+                self.u32(value.to_bits());
+                self.span(span);
+            },
+            Expression::String(ref value, span) => {
+                self.u8(1);
+                self.string(value);
+                self.span(span);
+            },
+            Expression::Name(ref name, span) => {
+                self.u8(2);
+                self.string(name);
+                self.span(span);
+            },
+            Expression::Binary(op, ref left, ref right, span) => {
+                self.u8(3);
+                self.operator(op);
+                self.expression(left);
+                self.expression(right);
+                self.span(span);
+            },
+            Expression::Unary(op, ref right, span) => {
+                self.u8(4);
+                self.operator(op);
+                self.expression(right);
+                self.span(span);
+            },
+            Expression::Conditional(ref condition, ref consequent, ref alternative, span) => {
+                self.u8(5);
+                self.expression(condition);
+                self.expression(consequent);
+                self.expression(alternative);
+                self.span(span);
+            },
+            Expression::Call(ref name, ref args, span) => {
+                self.u8(6);
+                self.string(name);
+                self.usize(args.len());
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.span(span);
+            },
+            Expression::Invalid(ref diagnostic) => {
+                self.u8(7);
+                self.string(diagnostic.code.as_str());
+                self.string(&diagnostic.message);
+                self.span(diagnostic.span);
+            }
+        }
+    }
+
+    fn operator(&mut self, op: Operator) {
+        self.u8(OPERATORS.iter().position(|o| *o == op).unwrap() as u8);
+    }
+
+    fn symbol(&mut self, symbol: &Symbol) {
+        match symbol.kind {
+            SymbolKind::Constant => self.u8(0),
+            SymbolKind::Variable => self.u8(1),
+            SymbolKind::Label => self.u8(2),
+            SymbolKind::Macro => self.u8(3)
+        }
+        self.expression(&symbol.value);
+        self.span(symbol.span);
+    }
+
+}
+
+
+// Reading --------------------------------------------------------------------
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    file_offset: usize,
+    kind: &'static str
+}
+
+impl<'a> Reader<'a> {
+
+    /// Checks the magic bytes and version at the start of the data
+    pub fn new(bytes: &'a [u8], magic: &[u8], version: u8, kind: &'static str) -> Result<Reader<'a>, String> {
+        let start = magic.len() + 1;
+        if bytes.len() < start || &bytes[..magic.len()] != magic {
+            Err(format!("Not a gbasm {}", kind))
+
+        } else if bytes[magic.len()] != version {
+            Err(format!("Unsupported {} version {} (expected {})", kind, bytes[magic.len()], version))
+
+        } else {
+            Ok(Reader {
+                bytes,
+                position: start,
+                file_offset: 0,
+                kind
+            })
+        }
+    }
+
+    /// Makes sure all of the data was read
+    pub fn finish(&self) -> Result<(), String> {
+        if self.position != self.bytes.len() {
+            Err(format!("Unexpected data at the end of the {}", self.kind))
+
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        match self.bytes.get(self.position..self.position + length) {
+            Some(bytes) => {
+                self.position += length;
+                Ok(bytes)
+            },
+            None => Err(format!("Unexpected end of {}", self.kind))
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, String> {
+        self.u32().map(|v| v as usize)
+    }
+
+    fn optional(&mut self) -> Result<Option<usize>, String> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => self.usize().map(Some)
+        }
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let length = self.usize()?;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| "Invalid string in object file".to_string())
+    }
+
+    fn span(&mut self) -> Result<Span, String> {
+        let span = Span {
+            file: self.usize()?,
+            start: self.usize()?,
+            end: self.usize()?,
+            line: self.usize()?,
+            column: self.usize()?
+        };
+
+        // Spans without a location stay empty
+        if span == Span::default() {
+            Ok(span)
+
+        } else {
+            Ok(Span {
+                file: span.file + self.file_offset,
+                .. span
+            })
+        }
+    }
+
+    fn section(&mut self) -> Result<Section, String> {
+
+        let name = self.string()?;
+        let region = match Region::all().get(self.u8()? as usize) {
+            Some(region) => *region,
+            None => return Err(invalid("region"))
+        };
+        let fixed_address = self.optional()?;
+        let fixed_bank = self.optional()?;
+        let mut section = Section::new(name, region, fixed_address, fixed_bank, self.span()?);
+
+        for _ in 0..self.usize()? {
+            let (kind, span, expansions) = self.entry()?;
+            section.add(kind, span);
+            if let Some(entry) = section.entries.last_mut() {
+                entry.expansions = expansions;
+            }
+        }
+
+        Ok(section)
+
+    }
+
+    fn entry(&mut self) -> Result<(EntryKind, Span, Vec<Expansion>), String> {
+
+        let kind = match self.u8()? {
+            0 => EntryKind::Label(self.string()?),
+            1 => {
+                let mnemonic = self.string()?;
+                let mut operands = vec![];
+                for _ in 0..self.usize()? {
+                    operands.push(self.operand()?);
+                }
+
+                // The opcode is selected again from the operands
+                match Instruction::new(&mnemonic, operands, Span::default()) {
+                    Ok(instruction) => EntryKind::Instruction(instruction),
+                    Err(_) => return Err(invalid("instruction"))
+                }
+            },
+            2 => {
+                let length = self.usize()?;
+                EntryKind::Bytes(self.take(length)?.to_vec())
+            },
+            3 => {
+                let width = if self.u8()? == 1 { Width::Byte } else { Width::Word };
+                EntryKind::Value(self.expression()?, width)
+            },
+            4 => {
+                let size = self.usize()?;
+                EntryKind::Space(size, self.optional()?.map(|f| f as u8))
+            },
+            _ => return Err(invalid("section entry"))
+        };
+
+        let span = self.span()?;
+        let mut expansions = vec![];
+        for _ in 0..self.usize()? {
+            expansions.push(Expansion {
+                name: self.string()?,
+                span: self.span()?,
+                iteration: self.optional()?
+            });
+        }
+
+        Ok((kind, span, expansions))
+
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        Ok(match self.u8()? {
+            0 => Operand::Register(self.string()?),
+            1 => Operand::Memory(self.string()?),
+            2 => Operand::Address(self.expression()?),
+            3 => Operand::Value(self.expression()?),
+            4 => Operand::StackOffset(self.expression()?),
+            5 => Operand::Offset(self.u32()? as i32),
+            _ => return Err(invalid("operand"))
+        })
+    }
+
+    fn expression(&mut self) -> Result<Expression, String> {
+        Ok(match self.u8()? {
+            0 => {
+                let value = f32::from_bits(self.u32()?);
+                Expression::Number(value, self.span()?)
+            },
+            1 => {
+                let value = self.string()?;
+                Expression::String(value, self.span()?)
+            },
+            2 => {
+                let name = self.string()?;
+                Expression::Name(name, self.span()?)
+            },
+            3 => {
+                let op = self.operator()?;
+                let left = self.expression()?;
+                let right = self.expression()?;
+                Expression::Binary(op, Box::new(left), Box::new(right), self.span()?)
+            },
+            4 => {
+                let op = self.operator()?;
+                let right = self.expression()?;
+                Expression::Unary(op, Box::new(right), self.span()?)
+            },
+            5 => {
+                let condition = self.expression()?;
+                let consequent = self.expression()?;
+                let alternative = self.expression()?;
+                Expression::Conditional(Box::new(condition), Box::new(consequent), Box::new(alternative), self.span()?)
+            },
+            6 => {
+                let name = self.string()?;
+                let mut args = vec![];
+                for _ in 0..self.usize()? {
+                    args.push(self.expression()?);
+                }
+                Expression::Call(name, args, self.span()?)
+            },
+            7 => {
+                let code = match ErrorCode::from_code(&self.string()?) {
+                    Some(code) => code,
+                    None => return Err(invalid("error code"))
+                };
+                let message = self.string()?;
+                Expression::Invalid(Diagnostic::error(code, message, self.span()?))
+            },
+            _ => return Err(invalid("expression"))
+        })
+    }
+
+    fn operator(&mut self) -> Result<Operator, String> {
+        match OPERATORS.get(self.u8()? as usize) {
+            Some(op) => Ok(*op),
+            None => Err(invalid("operator"))
+        }
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, String> {
+        let kind = match self.u8()? {
+            0 => SymbolKind::Constant,
+            1 => SymbolKind::Variable,
+            2 => SymbolKind::Label,
+            3 => SymbolKind::Macro,
+            _ => return Err(invalid("symbol"))
+        };
+        Ok(Symbol {
+            kind,
+            value: self.expression()?,
+            span: self.span()?
+        })
+    }
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn invalid(what: &str) -> String {
+    format!("Invalid {} in object file", what)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use compiler::{EntryKind, Expansion, Instruction, Operand, Region, Section, Symbol, SymbolKind, Width};
+    use parser::{Expression, Operator, Span};
+    use super::{ObjectFile, ObjectSource};
+
+    fn span(file: usize, line: usize, column: usize) -> Span {
+        Span {
+            file,
+            start: line * 10 + column,
+            end: line * 10 + column + 1,
+            line,
+            column
+        }
+    }
+
+    fn object() -> ObjectFile {
+
+        let call = Expression::Binary(
+            Operator::Plus,
+            Box::new(Expression::Name("engine_init".to_string(), span(0, 2, 9))),
+            Box::new(Expression::Number(1.5, span(0, 2, 21))),
+            span(0, 2, 9)
+        );
+
+        let mut section = Section::new("Main".to_string(), Region::RomX, Some(0x4000), Some(2), span(0, 1, 1));
+        section.add(EntryKind::Label("main".to_string()), span(0, 2, 1));
+        section.add(EntryKind::Instruction(Instruction::new(
+            "ld",
+            vec![Operand::Register("a".to_string()), Operand::Address(call.clone())],
+            span(0, 3, 5)
+
+        ).unwrap()), span(0, 3, 5));
+        section.add(EntryKind::Bytes(vec![1, 2, 3]), span(0, 4, 5));
+        section.add(EntryKind::Value(Expression::Unary(
+            Operator::UnaryMinus,
+            Box::new(Expression::String("a".to_string(), span(1, 1, 4))),
+            span(1, 1, 3)
+
+        ), Width::Word), span(1, 1, 1));
+        section.add(EntryKind::Space(16, Some(0xFF)), span(0, 5, 1));
+        section.entries[3].expansions = vec![
+            Expansion {
+                name: "foo".to_string(),
+                span: span(0, 6, 1),
+                iteration: None
+            },
+            Expansion {
+                name: "REPT".to_string(),
+                span: span(0, 7, 1),
+                iteration: Some(3)
+            }
+        ];
+
+        ObjectFile {
+            sources: vec![
+                ObjectSource {
+                    path: "src".to_string(),
+                    filename: "main.gb.s".to_string(),
+                    source: b"SECTION \"Main\"\nmain:\n".to_vec()
+                },
+                ObjectSource {
+                    path: "src".to_string(),
+                    filename: "data.gb.s".to_string(),
+                    source: vec![]
+                }
+            ],
+            sections: vec![section],
+            symbols: vec![
+                ("X".to_string(), Symbol {
+                    kind: SymbolKind::Constant,
+                    value: call,
+                    span: span(0, 8, 1)
+                }),
+                ("main".to_string(), Symbol {
+                    kind: SymbolKind::Label,
+                    value: Expression::Number(0.0, Span::default()),
+                    span: span(0, 2, 1)
+                })
+            ],
+            exports: vec!["main".to_string()]
+        }
+
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let object = object();
+        assert_eq!(ObjectFile::from_bytes(&object.to_bytes(), 0), Ok(object));
+    }
+
+    #[test]
+    fn test_file_offset() {
+        let object = ObjectFile::from_bytes(&object().to_bytes(), 3).unwrap();
+        assert_eq!(object.sections[0].span.file, 3);
+        assert_eq!(object.sections[0].entries[3].span.file, 4);
+        assert_eq!(object.sections[0].entries[3].expansions[1].span.file, 3);
+
+        // Spans without a location are not offset
+        assert_eq!(object.symbols[1].1.value, Expression::Number(0.0, Span::default()));
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut bytes = object().to_bytes();
+        assert!(ObjectFile::from_bytes(b"GBO", 0).is_err());
+        assert!(ObjectFile::from_bytes(b"GBARxxxx", 0).is_err());
+
+        bytes[4] = 0xFF;
+        assert!(ObjectFile::from_bytes(&bytes, 0).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = object().to_bytes();
+        for length in 5..bytes.len() {
+            assert!(ObjectFile::from_bytes(&bytes[..length], 0).is_err(), "accepted {} of {} bytes", length, bytes.len());
+        }
+    }
+
+}
@@ -0,0 +1,363 @@
+/// IPS offsets are 24 bit
+const IPS_MAX_OFFSET: usize = 0xFF_FFFF;
+
+/// IPS records are limited to 16 bit sizes
+const IPS_MAX_SIZE: usize = 0xFFFF;
+
+/// A record at this offset would be read as the "EOF" marker
+const IPS_EOF_OFFSET: usize = 0x45_4F46;
+
+/// Equal bytes between changes which are still merged into a single record
+/// since a new record would take more space
+const IPS_MERGE_GAP: usize = 5;
+
+/// BPS actions, the remaining target copy action is only supported when
+/// applying patches
+const SOURCE_READ: usize = 0;
+const TARGET_READ: usize = 1;
+const SOURCE_COPY: usize = 2;
+
+/// Creates a IPS patch which turns the base into the target image
+///
+/// Bytes past the end of the base are always written out, the target cannot
+/// be smaller than the base.
+pub fn create_ips(base: &[u8], target: &[u8]) -> Result<Vec<u8>, String> {
+
+    if target.len() < base.len() {
+        return Err("IPS patches cannot truncate the base image".to_string());
+
+    } else if target.len() > IPS_MAX_OFFSET + 1 {
+        return Err(format!("IPS patches cannot address images larger than {} bytes", IPS_MAX_OFFSET + 1));
+    }
+
+    let changed = |i: usize| base.get(i) != Some(&target[i]);
+
+    let mut patch = b"PATCH".to_vec();
+    let mut offset = 0;
+    while offset < target.len() {
+
+        if !changed(offset) {
+            offset += 1;
+            continue;
+        }
+
+        // Start one byte earlier to avoid the end of file marker
+        let start = if offset == IPS_EOF_OFFSET { offset - 1 } else { offset };
+
+        // Extend the record over all changes which are close enough
+        let mut end = offset + 1;
+        let mut next = end;
+        while next < target.len() && next - start < IPS_MAX_SIZE && next - end <= IPS_MERGE_GAP {
+            if changed(next) {
+                end = next + 1;
+            }
+            next += 1;
+        }
+
+        let size = end - start;
+        patch.extend_from_slice(&[(start >> 16) as u8, (start >> 8) as u8, start as u8]);
+        patch.extend_from_slice(&[(size >> 8) as u8, size as u8]);
+        patch.extend_from_slice(&target[start..end]);
+        offset = end;
+
+    }
+
+    patch.extend_from_slice(b"EOF");
+    Ok(patch)
+
+}
+
+/// Creates a BPS patch which turns the base into the target image
+///
+/// The patch is applied once after creation to validate the source, target
+/// and patch checksums it contains.
+pub fn create_bps(base: &[u8], target: &[u8]) -> Result<Vec<u8>, String> {
+
+    let mut patch = b"BPS1".to_vec();
+    write_number(&mut patch, base.len());
+    write_number(&mut patch, target.len());
+    write_number(&mut patch, 0);
+
+    // Unchanged bytes are read from the same offset in the source, all
+    // others are stored in the patch
+    let mut offset = 0;
+    while offset < target.len() {
+
+        let same = |i: usize| base.get(i) == Some(&target[i]);
+        let action = if same(offset) { SOURCE_READ } else { TARGET_READ };
+
+        let mut end = offset + 1;
+        while end < target.len() && same(end) == (action == SOURCE_READ) {
+            end += 1;
+        }
+
+        write_number(&mut patch, ((end - offset - 1) << 2) | action);
+        if action == TARGET_READ {
+            patch.extend_from_slice(&target[offset..end]);
+        }
+        offset = end;
+
+    }
+
+    let (source_crc, target_crc) = (crc32(base), crc32(target));
+    patch.extend_from_slice(&source_crc.to_le_bytes());
+    patch.extend_from_slice(&target_crc.to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+
+    if apply_bps(base, &patch)? != target {
+        return Err("Created BPS patch does not reproduce the target image".to_string());
+    }
+
+    Ok(patch)
+
+}
+
+/// Applies a BPS patch to the source after validating the patch and source
+/// checksums, the result is validated against the target checksum
+pub fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+
+    if patch.len() < 16 || &patch[0..4] != b"BPS1" {
+        return Err("Not a BPS patch".to_string());
+    }
+
+    let footer = patch.len() - 12;
+    let checksum = |offset: usize| u32::from_le_bytes([patch[offset], patch[offset + 1], patch[offset + 2], patch[offset + 3]]);
+    if crc32(&patch[..footer + 8]) != checksum(footer + 8) {
+        return Err("BPS patch checksum mismatch, the patch is corrupted".to_string());
+
+    } else if crc32(source) != checksum(footer) {
+        return Err(format!(
+            "BPS source checksum mismatch, expected {:0>8X} but the base image is {:0>8X}",
+            checksum(footer), crc32(source)
+        ));
+    }
+
+    let mut r = PatchReader {
+        bytes: &patch[..footer],
+        offset: 4
+    };
+
+    let source_size = r.number()?;
+    let target_size = r.number()?;
+    let metadata_size = r.number()?;
+    r.take(metadata_size)?;
+
+    if source_size != source.len() {
+        return Err(format!("BPS source size mismatch, expected {} bytes but the base image has {}", source_size, source.len()));
+    }
+
+    let mut target: Vec<u8> = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    while r.offset < r.bytes.len() {
+
+        let data = r.number()?;
+        let length = (data >> 2) + 1;
+        if target.len() + length > target_size {
+            return Err("BPS patch writes past the end of the target".to_string());
+        }
+
+        match data & 3 {
+            SOURCE_READ => {
+                let start = target.len();
+                target.extend_from_slice(source.get(start..start + length).ok_or("BPS patch reads past the end of the source")?);
+            },
+            TARGET_READ => target.extend_from_slice(r.take(length)?),
+            SOURCE_COPY => {
+                source_offset = r.relative(source_offset)?;
+                target.extend_from_slice(source.get(source_offset..source_offset + length).ok_or("BPS patch copies past the end of the source")?);
+                source_offset += length;
+            },
+            // Target copy
+            _ => {
+                target_offset = r.relative(target_offset)?;
+                if target_offset >= target.len() {
+                    return Err("BPS patch copies from past the end of the target".to_string());
+                }
+
+                // Copies may overlap with the bytes they produce
+                for _ in 0..length {
+                    let b = target[target_offset];
+                    target.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+
+    }
+
+    if target.len() != target_size {
+        return Err(format!("BPS target size mismatch, expected {} bytes but the patch produced {}", target_size, target.len()));
+
+    } else if crc32(&target) != checksum(footer + 4) {
+        return Err("BPS target checksum mismatch".to_string());
+    }
+
+    Ok(target)
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+struct PatchReader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> PatchReader<'a> {
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.bytes.get(self.offset..self.offset + len).ok_or("Unexpected end of BPS patch")?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let (mut data, mut shift) = (0usize, 1usize);
+        loop {
+            let x = usize::from(self.take(1)?[0]);
+            data = data.checked_add((x & 0x7F).checked_mul(shift).ok_or("Invalid number in BPS patch")?).ok_or("Invalid number in BPS patch")?;
+            if x & 0x80 != 0 {
+                return Ok(data);
+            }
+            shift = shift.checked_shl(7).ok_or("Invalid number in BPS patch")?;
+            data += shift;
+        }
+    }
+
+    fn relative(&mut self, offset: usize) -> Result<usize, String> {
+        let data = self.number()?;
+        let result = if data & 1 == 1 {
+            offset.checked_sub(data >> 1)
+
+        } else {
+            offset.checked_add(data >> 1)
+        };
+        result.ok_or_else(|| "Invalid relative offset in BPS patch".to_string())
+    }
+
+}
+
+fn write_number(patch: &mut Vec<u8>, mut data: usize) {
+    loop {
+        let x = (data & 0x7F) as u8;
+        data >>= 7;
+        if data == 0 {
+            patch.push(0x80 | x);
+            break;
+        }
+        patch.push(x);
+        data -= 1;
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, b| {
+        (0..8).fold(crc ^ u32::from(*b), |crc, _| {
+            if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 }
+        })
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{apply_bps, crc32, create_bps, create_ips, IPS_EOF_OFFSET};
+
+    /// Returns the offset and data of all records within a IPS patch
+    fn ips_records(patch: &[u8]) -> Vec<(usize, &[u8])> {
+        assert_eq!(&patch[..5], b"PATCH");
+        let mut records = vec![];
+        let mut offset = 5;
+        while &patch[offset..offset + 3] != b"EOF" {
+            let start = (patch[offset] as usize) << 16 | (patch[offset + 1] as usize) << 8 | patch[offset + 2] as usize;
+            let size = (patch[offset + 3] as usize) << 8 | patch[offset + 4] as usize;
+            records.push((start, &patch[offset + 5..offset + 5 + size]));
+            offset += 5 + size;
+        }
+        assert_eq!(offset + 3, patch.len());
+        records
+    }
+
+    fn apply_ips(base: &[u8], patch: &[u8]) -> Vec<u8> {
+        let mut target = base.to_vec();
+        for (start, data) in ips_records(patch) {
+            if target.len() < start + data.len() {
+                target.resize(start + data.len(), 0);
+            }
+            target[start..start + data.len()].copy_from_slice(data);
+        }
+        target
+    }
+
+    fn images() -> (Vec<u8>, Vec<u8>) {
+        let base: Vec<u8> = (0..0x8000).map(|i| (i * 7) as u8).collect();
+        let mut target = base.clone();
+        target[0x100] = 0xFF;
+        target[0x104] = 0xFF;
+        target[0x2000..0x2100].iter_mut().for_each(|b| *b = 0);
+        target.extend_from_slice(&[1, 2, 3]);
+        (base, target)
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_ips_round_trip() {
+        let (base, target) = images();
+        let patch = create_ips(&base, &target).unwrap();
+        assert_eq!(apply_ips(&base, &patch), target);
+
+        // Nearby changes share a record
+        assert_eq!(ips_records(&patch)[0], (0x100, &target[0x100..0x105]));
+
+        assert_eq!(create_ips(&base, &base).unwrap(), b"PATCHEOF");
+        assert!(create_ips(&target, &base).is_err());
+    }
+
+    #[test]
+    fn test_ips_eof_offset() {
+        let base = vec![0; IPS_EOF_OFFSET + 0x10];
+        let mut target = base.clone();
+        target[IPS_EOF_OFFSET] = 1;
+
+        let patch = create_ips(&base, &target).unwrap();
+        assert_eq!(ips_records(&patch), vec![(IPS_EOF_OFFSET - 1, &[0, 1][..])]);
+        assert_eq!(apply_ips(&base, &patch), target);
+    }
+
+    #[test]
+    fn test_bps_round_trip() {
+        let (base, target) = images();
+        let patch = create_bps(&base, &target).unwrap();
+        assert_eq!(apply_bps(&base, &patch), Ok(target.clone()));
+
+        // Targets can also be smaller than the base
+        let patch = create_bps(&target, &base).unwrap();
+        assert_eq!(apply_bps(&target, &patch), Ok(base.clone()));
+
+        // Patches only apply to the base they were created from
+        assert!(apply_bps(&target[1..], &patch).is_err());
+    }
+
+    #[test]
+    fn test_bps_corrupted() {
+        let (base, target) = images();
+        let patch = create_bps(&base, &target).unwrap();
+
+        let mut corrupted = patch.clone();
+        corrupted[patch.len() / 2] ^= 0xFF;
+        assert!(apply_bps(&base, &corrupted).is_err());
+
+        assert!(apply_bps(&base, &patch[..patch.len() - 1]).is_err());
+        assert!(apply_bps(&base, b"BPS1").is_err());
+        assert!(apply_bps(&base, &[0; 16]).is_err());
+    }
+
+}
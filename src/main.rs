@@ -4,6 +4,18 @@ extern crate gbasm;
 
 fn main() {
 
+    // Support attached warning options like "-Wno-unused" and "-Werror"
+    let mut argv = vec![];
+    for arg in std::env::args() {
+        match arg.strip_prefix("-W") {
+            Some(option) if !option.is_empty() => {
+                argv.push("-W".to_string());
+                argv.push(option.to_string());
+            },
+            _ => argv.push(arg)
+        }
+    }
+
     let args = clap::App::new("gbasm")
         .version(&crate_version!())
         .author("Ivo Wetzel <ivo.wetzel@googlemail.com>")
@@ -18,6 +30,50 @@ fn main() {
             .short("o")
             .takes_value(true)
         )
+        .arg(clap::Arg::with_name("split_banks")
+            .help("Writes every 16KB bank of the rom image into its own file instead (e.g. \"game.00.gb\", \"game.01.gb\")")
+            .long("split-banks")
+        )
+        .arg(clap::Arg::with_name("hexfile")
+            .help("Generates a Intel HEX file of the rom image")
+            .long("hex")
+            .takes_value(true)
+        )
+        .arg(clap::Arg::with_name("base")
+            .help("Assembles the sections into a existing rom image instead of a empty one, floating sections only use its unused space")
+            .long("base")
+            .takes_value(true)
+        )
+        .arg(clap::Arg::with_name("ipsfile")
+            .help("Generates a IPS patch from the base rom to the assembled rom, the rom image is only written with -o")
+            .long("ips")
+            .takes_value(true)
+            .requires("base")
+        )
+        .arg(clap::Arg::with_name("bpsfile")
+            .help("Generates a BPS patch from the base rom to the assembled rom, the rom image is only written with -o")
+            .long("bps")
+            .takes_value(true)
+            .requires("base")
+        )
+        .arg(clap::Arg::with_name("compile")
+            .help("Compiles the sources into a object file without linking them, object files (\".o\") can be passed as sources later on")
+            .short("c")
+            .conflicts_with_all(&["symfile", "mapfile", "listfile", "linefile", "jsonfile", "hexfile", "split_banks", "base", "optimize"])
+        )
+        .arg(clap::Arg::with_name("archive")
+            .help("Bundles the object files into a archive, only the members which define otherwise undefined names are linked from archives (\".a\")")
+            .long("archive")
+            .takes_value(true)
+            .conflicts_with("compile")
+        )
+        .arg(clap::Arg::with_name("define")
+            .help("Defines a constant before parsing (\"-D NAME\" defines NAME as 1, \"-D NAME=VALUE\" as VALUE)")
+            .short("D")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+        )
         .arg(clap::Arg::with_name("optimize")
             .help("Enable basic instruction optimizations")
             .short("O")
@@ -38,25 +94,80 @@ fn main() {
             .short("s")
             .takes_value(true)
         )
+        .arg(clap::Arg::with_name("sym_constants")
+            .help("Includes numeric constants and variables in the symbol map")
+            .long("sym-constants")
+            .requires("symfile")
+        )
+        .arg(clap::Arg::with_name("listfile")
+            .help("Generates a listing of every source line with its address and assembled bytes")
+            .short("l")
+            .takes_value(true)
+        )
+        .arg(clap::Arg::with_name("depfile")
+            .help("Generates a Makefile rule listing all files the written outputs depend on")
+            .short("M")
+            .takes_value(true)
+        )
+        .arg(clap::Arg::with_name("linefile")
+            .help("Generates a table which maps the rom addresses back to the files and lines they were assembled from")
+            .long("line-table")
+            .takes_value(true)
+        )
         .arg(clap::Arg::with_name("jsonfile")
             .help("Generates a JSON data dump of all sections with their data, labels, instructions etc")
             .short("j")
             .takes_value(true)
         )
+        .arg(clap::Arg::with_name("warnings")
+            .help("Enables (\"-WNAME\") or disables (\"-Wno-NAME\") a warning, \"-Werror\" turns all warnings into errors")
+            .short("W")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+        )
+        .arg(clap::Arg::with_name("max_errors")
+            .help("Stops after the given number of errors (default is 20, use 0 to report all errors)")
+            .long("max-errors")
+            .takes_value(true)
+        )
+        .arg(clap::Arg::with_name("diagnostics_format")
+            .help("The format of reported errors and warnings (\"human\" or \"json\" for one JSON object per line)")
+            .long("diagnostics-format")
+            .takes_value(true)
+            .possible_values(&["human", "json"])
+        )
+        .arg(clap::Arg::with_name("explain")
+            .help("Explains a error or warning code (e.g. \"E0301\") with examples")
+            .long("explain")
+            .takes_value(true)
+        )
         .arg(clap::Arg::with_name("silent")
-            .help("Surpresses all logging")
+            .help("Suppresses all logging and warnings, only errors are reported")
             .long("silent")
         )
         .arg(clap::Arg::with_name("unused")
-            .help("Report unused labels and variables")
+            .help("Report unused labels, constants and macros (use EXPORT to mark symbols as used)")
             .long("report-unused")
         )
         .arg(clap::Arg::with_name("verbose")
             .help("Provide increased logging")
             .long("verbose")
 
-        ).get_matches();
+        ).get_matches_from(argv);
+
 
+    if let Some(code) = args.value_of("explain") {
+        match gbasm::ErrorCode::from_code(code) {
+            Some(error) => println!("{}: {}", error.as_str(), gbasm::explain(error)),
+            None => {
+                use std::io::{Write, stderr};
+                writeln!(&mut stderr(), "Unknown error code \"{}\"", code).ok();
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
     match args.values_of("sources") {
 
@@ -67,10 +178,105 @@ fn main() {
                 args.is_present("verbose")
             );
 
-            // Compile Source Files
-            if let Err(message) = c.compile_source_files(sources, !args.is_present("optimize")) {
+            // Bundle object files without compiling anything
+            if let Some(archive) = args.value_of("archive") {
+                if let Err(message) = c.generate_archive(archive, sources) {
+                    use std::io::{Write, stderr};
+                    writeln!(&mut stderr(), "{}", message).ok();
+                    std::process::exit(1);
+                }
+                return;
+            }
+
+            // Support reproducible builds by allowing to override the build time
+            if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+                match epoch.parse() {
+                    Ok(timestamp) => c.set_build_time(timestamp),
+                    Err(_) => {
+                        use std::io::{Write, stderr};
+                        writeln!(&mut stderr(), "Invalid SOURCE_DATE_EPOCH \"{}\"", epoch).ok();
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            if let Some(limit) = args.value_of("max_errors") {
+                match limit.parse() {
+                    Ok(limit) => c.set_error_limit(limit),
+                    Err(_) => {
+                        use std::io::{Write, stderr};
+                        writeln!(&mut stderr(), "Invalid error limit \"{}\"", limit).ok();
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            for option in args.values_of("warnings").unwrap_or(vec![]) {
+                if let Err(message) = c.configure_warning(option) {
+                    use std::io::{Write, stderr};
+                    writeln!(&mut stderr(), "Invalid warning option \"-W{}\": {}", option, message).ok();
+                    std::process::exit(1);
+                }
+            }
+
+            // Report diagnostics as text or as one JSON object per line
+            let json = args.value_of("diagnostics_format") == Some("json");
+            let format = |c: &gbasm::Compiler, diagnostic: &gbasm::Diagnostic| {
+                use std::io::{IsTerminal, stderr};
+                if json {
+                    c.format_diagnostic_json(diagnostic)
+
+                } else {
+                    format!("{}\n", c.format_diagnostic(diagnostic, stderr().is_terminal()))
+                }
+            };
+
+            // Define command line constants
+            for define in args.values_of("define").unwrap_or(vec![]) {
+                let mut parts = define.splitn(2, '=');
+                let name = parts.next().unwrap_or("");
+                if let Err(diagnostic) = c.define_symbol(name, parts.next().unwrap_or("1")) {
+                    use std::io::{Write, stderr};
+                    let diagnostic = diagnostic.with_note(format!("in command line definition \"{}\"", define));
+                    writeln!(&mut stderr(), "{}", format(&c, &diagnostic)).ok();
+                    std::process::exit(1);
+                }
+            }
+
+            // Load the base rom to assemble into
+            if let Some(base) = args.value_of("base") {
+                if let Err(message) = c.load_base_rom(base) {
+                    use std::io::{Write, stderr};
+                    writeln!(&mut stderr(), "{}", message).ok();
+                    std::process::exit(1);
+                }
+            }
+
+            // Compile Source Files and report all errors and warnings
+            c.set_compile_only(args.is_present("compile"));
+            let result = c.compile_source_files(sources, !args.is_present("optimize"));
+            {
                 use std::io::{Write, stderr};
-                writeln!(&mut stderr(), "Compilation error: {}", message).ok();
+                for diagnostic in c.diagnostics() {
+
+                    // Silent mode only reports errors
+                    if args.is_present("silent") && !diagnostic.is_error() {
+                        continue;
+                    }
+
+                    writeln!(&mut stderr(), "{}", format(&c, diagnostic)).ok();
+                }
+            }
+
+            if let Err(errors) = result {
+                if !json {
+                    use std::io::{Write, stderr};
+                    writeln!(
+                        &mut stderr(),
+                        "For more information about an error, try `gbasm --explain {}`",
+                        errors[0].code.as_str()
+                    ).ok();
+                }
                 std::process::exit(1);
             }
 
@@ -79,27 +285,97 @@ fn main() {
                 c.optimize_instructions(args.is_present("unsafe"));
             }
 
-            // Report unused variables
+            // Report unused symbols
             if args.is_present("unused") {
-                println!("{}", c.report_unsused());
+                println!("{}", c.report_unused());
             }
 
-            // Generate ROM image
-            c.generate_rom_image(args.value_of("outfile").unwrap_or("game.gb"));
+            // Generate ROM image or object file
+            let object = std::path::Path::new(sources[0]).with_extension("o");
+            let target = if args.is_present("compile") {
+                args.value_of("outfile").unwrap_or_else(|| object.to_str().unwrap_or("out.o"))
+
+            } else {
+                args.value_of("outfile").unwrap_or("game.gb")
+            };
+
+            // Patches replace the rom image unless a output file is given
+            let patch_only = (args.is_present("ipsfile") || args.is_present("bpsfile")) && !args.is_present("outfile");
+
+            // The names of all written files are the targets of the dependency file
+            let mut outputs = vec![];
+            let mut targets: Vec<String> = vec![];
+            if args.is_present("compile") {
+                outputs.push(c.generate_object_file(target));
+                targets.push(target.to_string());
+
+            } else if args.is_present("split_banks") {
+                outputs.push(c.generate_bank_files(target).map(|files| targets.extend(files)));
+
+            } else if !patch_only {
+                outputs.push(c.generate_rom_image(target));
+                targets.push(target.to_string());
+            }
+
+            // Generate IPS patch
+            if let Some(ipsfile) = args.value_of("ipsfile") {
+                outputs.push(c.generate_ips_patch(ipsfile));
+                targets.push(ipsfile.to_string());
+            }
+
+            // Generate BPS patch
+            if let Some(bpsfile) = args.value_of("bpsfile") {
+                outputs.push(c.generate_bps_patch(bpsfile));
+                targets.push(bpsfile.to_string());
+            }
+
+            // Generate Intel HEX file
+            if let Some(hexfile) = args.value_of("hexfile") {
+                outputs.push(c.generate_hex_file(hexfile));
+                targets.push(hexfile.to_string());
+            }
 
             // Generates symbol file
-            if let Some(ref symfile) = args.value_of("symfile") {
-                c.generate_symbol_file(symfile);
+            if let Some(symfile) = args.value_of("symfile") {
+                outputs.push(c.generate_symbol_file(symfile, args.is_present("sym_constants")));
+                targets.push(symfile.to_string());
             }
 
             // Generate mapping file
-            if let Some(ref mapfile) = args.value_of("mapfile") {
-                c.generate_mapping_file(mapfile);
+            if let Some(mapfile) = args.value_of("mapfile") {
+                outputs.push(c.generate_mapping_file(mapfile));
+                targets.push(mapfile.to_string());
+            }
+
+            // Generate listing file
+            if let Some(listfile) = args.value_of("listfile") {
+                outputs.push(c.generate_listing_file(listfile));
+                targets.push(listfile.to_string());
+            }
+
+            // Generate line table
+            if let Some(linefile) = args.value_of("linefile") {
+                outputs.push(c.generate_line_table(linefile));
+                targets.push(linefile.to_string());
             }
 
             // Generate json file
-            if let Some(ref jsonfile) = args.value_of("jsonfile") {
-                c.generate_json_file(jsonfile);
+            if let Some(jsonfile) = args.value_of("jsonfile") {
+                outputs.push(c.generate_json_file(jsonfile));
+                targets.push(jsonfile.to_string());
+            }
+
+            // Generate dependency file
+            if let Some(depfile) = args.value_of("depfile") {
+                outputs.push(c.generate_dependency_file(depfile, &targets));
+            }
+
+            for result in outputs {
+                if let Err(message) = result {
+                    use std::io::{Write, stderr};
+                    writeln!(&mut stderr(), "{}", message).ok();
+                    std::process::exit(1);
+                }
             }
 
         },
@@ -1,23 +1,46 @@
-use compiler::SourceIter;
+use compiler::{Diagnostic, ErrorCode, SourceIter};
 use parser::Operator;
+use parser::Span;
 use parser::Token;
 
 /// Low Level Assembly Tokenizer which only returns uncombined tokens
 pub struct BaseLexer<'a> {
-    source: &'a mut SourceIter
+    source: &'a mut SourceIter,
+
+    /// Whether no other token was read on the current line yet, only names
+    /// at the start of a line can be label definitions
+    line_start: bool
 }
 
 impl<'a> Iterator for BaseLexer<'a> {
 
-    type Item = Token;
+    type Item = (Token, Span);
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            let start = self.source.span();
             match self.next_raw_token() {
                 Token::Whitespace | Token::Comment(_) => {
                     continue;
                 },
-                token => return Some(token)
+                mut token => {
+
+                    let mut span = start.until(self.source.span());
+
+                    // Make sure errors always point at the offending character
+                    // and continue lexing on the next line
+                    if let Token::Error(ref mut diagnostic) = token {
+                        if span.end == span.start {
+                            span.end += 1;
+                        }
+                        diagnostic.span = span;
+                        self.skip_line();
+                    }
+
+                    self.line_start = token == Token::Newline;
+                    return Some((token, span))
+
+                }
             }
         }
     }
@@ -32,7 +55,8 @@ impl <'a>BaseLexer<'a> {
         source.next();
 
         BaseLexer {
-            source: source
+            source: source,
+            line_start: true
         }
 
     }
@@ -44,8 +68,11 @@ impl <'a>BaseLexer<'a> {
 
         match ch {
 
-            // Newlines
+            // Newlines, with \r\n being treated as a single line break
             b'\r' | b'\n' => {
+                if ch == b'\r' && next == b'\n' {
+                    self.source.next();
+                }
                 self.source.next();
                 Token::Newline
             },
@@ -100,13 +127,13 @@ impl <'a>BaseLexer<'a> {
             }
 
             // Parse local Labels
-            b'.' if is_name_start(next) => self.parse_local_label(),
+            b'.' if is_name_start(next) || next == b'{' => self.parse_local_label(),
 
             // Skip Whitespace
             _ if is_whitespace(ch) => self.parse_whitespace(),
 
             // Parse Names and global Labels
-            _ if is_name_start(ch) => self.parse_name(),
+            _ if is_name_start(ch) || ch == b'{' => self.parse_name(),
 
             // Parse positive Decimal Numbers
             _ if is_decimal(ch) => self.parse_decimal(false),
@@ -118,12 +145,18 @@ impl <'a>BaseLexer<'a> {
             0 => Token::Eof,
 
             // Unkown symbols
-            _ => Token::Error(format!("Unexpected character \"{}\" ({})", "", ch))
+            _ => error(ErrorCode::UnexpectedCharacter, format!("Unexpected character \"{}\" ({})", ch as char, ch))
 
         }
 
     }
 
+    fn skip_line(&mut self) {
+        while !self.source.is_empty() && !is_newline(self.source.get()) {
+            self.source.next();
+        }
+    }
+
     fn parse_comment(&mut self) -> Token {
 
         let mut bytes = vec![];
@@ -151,7 +184,7 @@ impl <'a>BaseLexer<'a> {
         let mut bytes: Vec<u8> = Vec::new();
         let mut ch: u8 = self.source.next();
 
-        while ch != delimiter && !self.source.is_empty() {
+        while ch != delimiter && !is_newline(ch) && !self.source.is_empty() {
 
             // Escape sequences
             if ch == b'\\' {
@@ -166,7 +199,7 @@ impl <'a>BaseLexer<'a> {
                     b'\'' => 39,
                     b'\\' => 92,
                     c => {
-                        return Token::Error(format!("Unkown character escape sequence \"{}\" in string literal", c))
+                        return error(ErrorCode::InvalidEscapeSequence, format!("Unkown character escape sequence \"\\{}\" in string literal", c as char))
                     }
                 });
 
@@ -179,13 +212,13 @@ impl <'a>BaseLexer<'a> {
         }
 
         if ch != delimiter {
-            Token::Error("Unclosed string literal".to_string())
+            error(ErrorCode::UnclosedString, "Unclosed string literal".to_string())
 
         } else {
             self.source.next();
             match String::from_utf8(bytes) {
                 Ok(v) => Token::String(v),
-                Err(_) => Token::Error("Invalid string literal contents".to_string())
+                Err(_) => error(ErrorCode::InvalidStringContents, "Invalid string literal contents".to_string())
             }
         }
 
@@ -241,6 +274,9 @@ impl <'a>BaseLexer<'a> {
 
             // Single Character Operatots
             (_, _) => match ch {
+                b'=' => Token::Directive("=".to_string()),
+                b'?' => Token::Operator(Operator::Conditional),
+                b':' => Token::Operator(Operator::ConditionalElse),
                 b'>' => Token::Operator(Operator::GreaterThan),
                 b'<' => Token::Operator(Operator::LessThan),
                 b'!' => Token::Operator(Operator::UnaryNot),
@@ -253,7 +289,7 @@ impl <'a>BaseLexer<'a> {
                 b'|' => Token::Operator(Operator::BitwiseOr),
                 b'~' => Token::Operator(Operator::Negate),
                 b'^' => Token::Operator(Operator::BitwiseXor),
-                _ => Token::Error(format!("Invalid operator \"{}\"", ch))
+                _ => error(ErrorCode::InvalidOperator, format!("Invalid operator \"{}\"", ch as char))
             }
 
         }
@@ -265,7 +301,7 @@ impl <'a>BaseLexer<'a> {
         let (digit, number, len) = self.parse_decimal_part();
 
         if len == 8 {
-            Token::Error("Decimal literal exceeds maximum length of 8 digits".to_string())
+            error(ErrorCode::NumberTooLong, "Decimal literal exceeds maximum length of 8 digits".to_string())
 
         // Floats
         } else if digit == b'.' {
@@ -325,7 +361,7 @@ impl <'a>BaseLexer<'a> {
                 digit = self.source.next();
 
             } else if bytes.len() == 9 {
-                return Token::Error("Binary literal exceeds maximum length of 8 digits".to_string());
+                return error(ErrorCode::NumberTooLong, "Binary literal exceeds maximum length of 8 digits".to_string());
             }
 
         }
@@ -359,7 +395,7 @@ impl <'a>BaseLexer<'a> {
                 digit = self.source.next();
 
             } else if bytes.len() == 5 {
-                return Token::Error("Hex literal exceeds maximum length of 4 digits".to_string());
+                return error(ErrorCode::NumberTooLong, "Hex literal exceeds maximum length of 4 digits".to_string());
             }
 
         }
@@ -371,12 +407,10 @@ impl <'a>BaseLexer<'a> {
     fn parse_name(&mut self) -> Token {
 
         let mut bytes: Vec<u8> = Vec::new();
-        let mut ch = self.source.get();
-
-        while is_name_part(ch) {
-            bytes.push(ch);
-            ch = self.source.next();
-        }
+        let ch = match self.parse_name_bytes(&mut bytes) {
+            Ok(ch) => ch,
+            Err(token) => return token
+        };
 
         let name = string_from_bytes(bytes);
 
@@ -394,17 +428,23 @@ impl <'a>BaseLexer<'a> {
 
         } else if name != "" {
 
-            // Global Label Definitions
-            if ch == b':' {
+            // Global Label Definitions, elsewhere the colon belongs to a
+            // conditional expression
+            if ch == b':' && self.line_start {
                 self.source.next();
                 Token::GlobalLabelDef(name)
 
+            // Post increment and decrement memory operands "[hl+]" and "[hl-]"
+            } else if name == "hl" && (ch == b'+' || ch == b'-') && self.source.peek() == b']' {
+                self.source.next();
+                Token::Name(if ch == b'+' { "hli" } else { "hld" }.to_string())
+
             } else {
                 Token::Name(name)
             }
 
         } else {
-            Token::Error(format!("Unexpected empty name"))
+            error(ErrorCode::EmptyName, "Unexpected empty name".to_string())
         }
 
     }
@@ -412,15 +452,15 @@ impl <'a>BaseLexer<'a> {
     fn parse_local_label(&mut self) -> Token {
 
         let mut bytes: Vec<u8> = vec![self.source.get()];
-        let mut ch = self.source.next();
+        self.source.next();
 
-        while is_name_part(ch) {
-            bytes.push(ch);
-            ch = self.source.next();
-        }
+        let ch = match self.parse_name_bytes(&mut bytes) {
+            Ok(ch) => ch,
+            Err(token) => return token
+        };
 
         // Label Definition
-        if ch == b':' {
+        if ch == b':' && self.line_start {
             self.source.next();
             Token::LocalLabelDef(string_from_bytes(bytes))
 
@@ -431,6 +471,39 @@ impl <'a>BaseLexer<'a> {
 
     }
 
+    fn parse_name_bytes(&mut self, bytes: &mut Vec<u8>) -> Result<u8, Token> {
+
+        let mut ch = self.source.get();
+
+        loop {
+
+            if is_name_part(ch) {
+                bytes.push(ch);
+                ch = self.source.next();
+
+            // Names can contain {SYMBOL} interpolations which are resolved
+            // during evaluation
+            } else if ch == b'{' {
+
+                while ch != b'}' {
+                    if ch == 0 || is_newline(ch) {
+                        return Err(error(ErrorCode::UnclosedInterpolation, "Unclosed interpolation in name".to_string()));
+                    }
+                    bytes.push(ch);
+                    ch = self.source.next();
+                }
+
+                bytes.push(ch);
+                ch = self.source.next();
+
+            } else {
+                return Ok(ch);
+            }
+
+        }
+
+    }
+
     fn parse_offset_or_macro_arg(&mut self) -> Token {
 
         let sign = self.source.next();
@@ -459,7 +532,7 @@ impl <'a>BaseLexer<'a> {
             Token::MacroArg(string_from_bytes(bytes))
 
         } else{
-            Token::Error(format!("Unexpected \"{}\", expected a valid direction specifier (- or +) instead", sign))
+            error(ErrorCode::InvalidOffset, format!("Unexpected \"{}\", expected a valid direction specifier (- or +) instead", sign as char))
         }
 
     }
@@ -468,6 +541,10 @@ impl <'a>BaseLexer<'a> {
 
 
 // Helpers --------------------------------------------------------------------
+fn error(code: ErrorCode, message: String) -> Token {
+    Token::Error(Diagnostic::error(code, message, Span::default()))
+}
+
 fn string_from_bytes(bytes: Vec<u8>) -> String {
     match String::from_utf8(bytes) {
         Ok(v) => v,
@@ -515,7 +592,9 @@ fn is_instruction(name: &str) -> bool {
         "daa" => true,
         "dec" => true,
         "inc" => true,
+        "ldd" => true,
         "ldh" => true,
+        "ldi" => true,
         "nop" => true,
         "pop" => true,
         "res" => true,
@@ -556,11 +635,20 @@ fn is_directive(name: &str) -> bool {
 
         "EQU" => true,
         "EQUS" => true,
+        "SET" => true,
 
         "BANK" => true,
 
         "INCBIN" => true,
 
+        "REPT" => true,
+        "ENDR" => true,
+
+        "NOWARN" => true,
+        "ENDNOWARN" => true,
+
+        "EXPORT" => true,
+
         "SECTION" => true,
         "INCLUDE" => true,
 
@@ -630,9 +718,11 @@ fn is_operator(c: u8) -> bool {
         b'+' => true,
         b'-' => true,
         b'/' => true,
+        b':' => true,
         b'<' => true,
         b'=' => true,
         b'>' => true,
+        b'?' => true,
         b'^' => true,
         b'|' => true,
         b'~' => true,
@@ -640,3 +730,47 @@ fn is_operator(c: u8) -> bool {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+
+    use compiler::SourceString;
+    use parser::{BaseLexer, Operator, Token};
+
+    fn tokens(source: &str) -> Vec<Token> {
+        let mut source = SourceString::new("test", source);
+        BaseLexer::new(&mut source).map(|(token, _)| token).take_while(|t| *t != Token::Eof).collect()
+    }
+
+    #[test]
+    fn test_conditional_else_without_space() {
+        assert_eq!(tokens("DB c ? a: b"), vec![
+            Token::Directive("DB".to_string()),
+            Token::Name("c".to_string()),
+            Token::Operator(Operator::Conditional),
+            Token::Name("a".to_string()),
+            Token::Operator(Operator::ConditionalElse),
+            Token::Name("b".to_string())
+        ]);
+        assert_eq!(tokens("DB c ? .a: .b"), vec![
+            Token::Directive("DB".to_string()),
+            Token::Name("c".to_string()),
+            Token::Operator(Operator::Conditional),
+            Token::LocalLabelRef(".a".to_string()),
+            Token::Operator(Operator::ConditionalElse),
+            Token::LocalLabelRef(".b".to_string())
+        ]);
+    }
+
+    #[test]
+    fn test_label_definitions_at_line_start() {
+        assert_eq!(tokens("a: nop\n  .b: nop"), vec![
+            Token::GlobalLabelDef("a".to_string()),
+            Token::Instruction("nop".to_string()),
+            Token::Newline,
+            Token::LocalLabelDef(".b".to_string()),
+            Token::Instruction("nop".to_string())
+        ]);
+    }
+
+}
@@ -1,206 +1,614 @@
+use compiler::{Diagnostic, ErrorCode};
 use parser::Operator;
+use parser::Span;
 use parser::Token;
+use parser::TokenType;
+use parser::format::{format_string, interpolate};
 
-#[derive(Debug, PartialEq)]
+/// The maximum length of a string created by repeating another one
+const MAX_STRING_LENGTH: usize = 65536;
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Number(f32),
-    String(String),
-    Name(String),
-    Binary(Operator, Box<Expression>, Box<Expression>),
-    Unary(Operator, Box<Expression>),
-    Call(String, Vec<Expression>),
-    Invalid(String)
+    Number(f32, Span),
+    String(String, Span),
+    Name(String, Span),
+    Binary(Operator, Box<Expression>, Box<Expression>, Span),
+    Unary(Operator, Box<Expression>, Span),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>, Span),
+    Call(String, Vec<Expression>, Span),
+    Invalid(Diagnostic)
 }
 
 impl Expression {
 
-    /// A Implementation of the Shunting Yard Algorithm
-    pub fn new(tokens: Vec<Token>) -> Expression {
+    /// Builds a expression tree from a list of tokens wrapped in
+    /// parenthesis, malformed expressions result in a Invalid expression
+    pub fn new(tokens: Vec<(Token, Span)>) -> Expression {
+        match parse_expression(tokens) {
+            Ok(expression) => expression,
+            Err(diagnostic) => Expression::Invalid(diagnostic)
+        }
+    }
 
-        let mut values: Vec<Expression> = vec![];
-        let mut operators: Vec<Operator> = vec![];
-        let mut valid_unary_position = false;
-        let mut is_callable = false;
+    /// Returns the location of the expression within its source
+    pub fn span(&self) -> Span {
+        match *self {
+            Expression::Number(_, span) |
+            Expression::String(_, span) |
+            Expression::Name(_, span) |
+            Expression::Binary(_, _, _, span) |
+            Expression::Unary(_, _, span) |
+            Expression::Conditional(_, _, _, span) |
+            Expression::Call(_, _, span) => span,
+            Expression::Invalid(ref diagnostic) => diagnostic.span
+        }
+    }
 
-        for token in tokens {
+    /// Returns a copy of a evaluated value which points to another location
+    pub fn with_span(self, span: Span) -> Expression {
+        match self {
+            Expression::Number(value, _) => Expression::Number(value, span),
+            Expression::String(value, _) => Expression::String(value, span),
+            Expression::Invalid(diagnostic) => Expression::Invalid(diagnostic.with_span(span)),
+            expression => expression
+        }
+    }
 
-            match token {
+    /// Calls the function with every name which is referenced within the
+    /// expression tree
+    pub fn visit_names<F>(&self, visit: &mut F) where F: FnMut(&str) {
+        match *self {
+            Expression::Name(ref name, _) => visit(name),
+            Expression::Binary(_, ref left, ref right, _) => {
+                left.visit_names(visit);
+                right.visit_names(visit);
+            },
+            Expression::Unary(_, ref right, _) => right.visit_names(visit),
+            Expression::Conditional(ref condition, ref consequent, ref alternative, _) => {
+                condition.visit_names(visit);
+                consequent.visit_names(visit);
+                alternative.visit_names(visit);
+            },
+            Expression::Call(_, ref args, _) => {
+                for arg in args {
+                    arg.visit_names(visit);
+                }
+            },
+            Expression::Number(_, _) | Expression::String(_, _) | Expression::Invalid(_) => {}
+        }
+    }
 
-                // TODO handle macro args
-                Token::Number(value) => {
-                    values.push(Expression::Number(value));
-                    is_callable = false;
-                    valid_unary_position = false;
+    /// Replaces all names which the resolver returns a value for, the
+    /// remaining names are kept so the expression can be evaluated later on
+    pub fn substitute<F>(&self, resolve: &mut F) -> Expression where F: FnMut(&str) -> Option<Expression> {
+        match *self {
+            Expression::String(ref value, span) => match interpolate(value, resolve, span) {
+                Ok(value) => Expression::String(value, span),
+                Err(_) => self.clone()
+            },
+            Expression::Name(ref name, span) => match interpolate(name, resolve, span) {
+                Ok(name) => match resolve(&name) {
+                    Some(Expression::Name(name, _)) => Expression::Name(name, span),
+                    Some(value) => value.with_span(span),
+                    None => Expression::Name(name, span)
                 },
+                Err(_) => self.clone()
+            },
+            Expression::Binary(op, ref left, ref right, span) => {
+                Expression::Binary(op, Box::new(left.substitute(resolve)), Box::new(right.substitute(resolve)), span)
+            },
+            Expression::Unary(op, ref right, span) => {
+                Expression::Unary(op, Box::new(right.substitute(resolve)), span)
+            },
+            Expression::Conditional(ref condition, ref consequent, ref alternative, span) => {
+                Expression::Conditional(
+                    Box::new(condition.substitute(resolve)),
+                    Box::new(consequent.substitute(resolve)),
+                    Box::new(alternative.substitute(resolve)),
+                    span
+                )
+            },
+            Expression::Call(ref name, ref args, span) => {
+                Expression::Call(name.clone(), args.iter().map(|arg| arg.substitute(resolve)).collect(), span)
+            },
+            Expression::Number(_, _) | Expression::Invalid(_) => self.clone()
+        }
+    }
 
-                Token::String(string) => {
-                    values.push(Expression::String(string));
-                    is_callable = false;
-                    valid_unary_position = false;
+    /// Evaluates the expression tree into either a Number or a String, names
+    /// are looked up via the passed resolver
+    pub fn evaluate<F>(&self, resolve: &mut F) -> Expression where F: FnMut(&str) -> Option<Expression> {
+        match *self {
+            Expression::Number(value, span) => Expression::Number(value, span),
+            Expression::String(ref value, span) => match interpolate(value, resolve, span) {
+                Ok(value) => Expression::String(value, span),
+                Err(diagnostic) => Expression::Invalid(diagnostic)
+            },
+            Expression::Name(ref name, span) => match interpolate(name, resolve, span) {
+                Ok(name) => match resolve(&name) {
+                    // Constants which refer to names from other object files
+                    // are only evaluated once they are used
+                    Some(value @ Expression::Binary(..)) | Some(value @ Expression::Unary(..)) |
+                    Some(value @ Expression::Conditional(..)) | Some(value @ Expression::Call(..)) |
+                    Some(value @ Expression::Name(..)) => value.evaluate(resolve).with_span(span),
+                    Some(value) => value.with_span(span),
+                    None => invalid(ErrorCode::UndefinedName, format!("Undefined name \"{}\"", name), span)
                 },
+                Err(diagnostic) => Expression::Invalid(diagnostic)
+            },
+            Expression::Binary(op, ref left, ref right, span) => {
+                evaluate_binary(op, left.evaluate(resolve), right.evaluate(resolve), span)
+            },
+            Expression::Unary(op, ref right, span) => {
+                evaluate_unary(op, right.evaluate(resolve), span)
+            },
 
-                Token::LParen => {
+            // Only the selected branch is evaluated, so the other one may
+            // refer to names which are not defined
+            Expression::Conditional(ref condition, ref consequent, ref alternative, span) => {
+                match condition.evaluate(resolve) {
+                    Expression::Number(value, _) if value != 0.0 => consequent.evaluate(resolve),
+                    Expression::String(ref value, _) if !value.is_empty() => consequent.evaluate(resolve),
+                    Expression::Number(_, _) | Expression::String(_, _) => alternative.evaluate(resolve),
+                    Expression::Invalid(diagnostic) => Expression::Invalid(diagnostic),
+                    _ => invalid(ErrorCode::InvalidOperands, "Invalid condition for conditional expression".to_string(), span)
+                }
+            },
+            Expression::Call(ref name, ref args, span) => {
 
-                    if is_callable {
-                        operators.push(Operator::Call);
+                let mut values = vec![];
+                for arg in args {
+                    match arg.evaluate(resolve) {
+                        Expression::Invalid(diagnostic) => return Expression::Invalid(diagnostic),
+                        value => values.push(value)
                     }
+                }
 
-                    operators.push(Operator::Paren);
-                    is_callable = false;
-                    valid_unary_position = true;
+                call_function(name, values, span)
 
-                },
+            },
+            Expression::Invalid(ref diagnostic) => Expression::Invalid(diagnostic.clone())
+        }
+    }
 
-                Token::Name(name) => {
-                    values.push(Expression::Name(name));
-                    is_callable = true;
-                    valid_unary_position = false;
-                },
+}
 
-                Token::Operator(mut op) => {
+/// A Implementation of the Shunting Yard Algorithm
+fn parse_expression(tokens: Vec<(Token, Span)>) -> Result<Expression, Diagnostic> {
 
-                    // Unary: Follows another operator or a left paren
-                    if valid_unary_position {
+    let mut values: Vec<Expression> = vec![];
+    let mut operators: Vec<(Operator, Span)> = vec![];
+    let mut calls: Vec<usize> = vec![];
+    let mut valid_unary_position = false;
+    let mut is_callable = false;
+    let mut last_token = TokenType::Begin;
+    let end = tokens.last().map(|&(_, span)| span).unwrap_or_default();
 
-                        // TODO check if we got a valid unary operator
+    for (token, span) in tokens {
 
-                        // Replace normal minus with its unary equivalant
-                        if op == Operator::Minus {
-                            op = Operator::UnaryMinus;
-                        }
+        let token_type = token.to_type();
+        match token {
 
-                    // Binary: Follows an operand or a right paren
-                    } else {
-                        consume_operator(&mut values, &mut operators, op.get_prec());
-                    }
+            Token::Number(value) => {
+                values.push(Expression::Number(value, span));
+                is_callable = false;
+                valid_unary_position = false;
+            },
 
-                    operators.push(op);
+            Token::String(string) => {
+                values.push(Expression::String(string, span));
+                is_callable = false;
+                valid_unary_position = false;
+            },
 
-                    is_callable = false;
-                    valid_unary_position = true;
+            Token::LParen => {
 
-                },
+                // Remember where the arguments of the call start
+                if is_callable {
+                    operators.push((Operator::Call, span));
+                    calls.push(values.len());
+                }
+
+                operators.push((Operator::Paren, span));
+                is_callable = false;
+                valid_unary_position = true;
+
+            },
+
+            Token::Name(name) | Token::LocalLabelRef(name) => {
+                values.push(Expression::Name(name, span));
+                is_callable = true;
+                valid_unary_position = false;
+            },
+
+            // Macro arguments are kept as names which are replaced by their
+            // values when the macro is expanded
+            Token::MacroArg(name) => {
+                values.push(Expression::Name(format!("@{}", name), span));
+                is_callable = false;
+                valid_unary_position = false;
+            },
+
+            // Conditionals: Find the matching ? and turn it into the
+            // alternative branch of the conditional
+            Token::Operator(Operator::ConditionalElse) => {
+
+                while match operators.last() {
+                    Some(&(op, _)) => op != Operator::Conditional && op != Operator::Paren,
+                    None => false
+                } {
+                    pop_operator(&mut values, &mut operators)?;
+                }
+
+                if let Some(&(Operator::Conditional, _)) = operators.last() {
+                    operators.pop();
+                    operators.push((Operator::ConditionalElse, span));
+
+                } else {
+                    return Err(error(
+                        ErrorCode::UnexpectedConditionalElse,
+                        "Unexpected \":\" without a matching \"?\"".to_string(),
+                        span
+                    ));
+                }
 
-                Token::RParen | Token::Comma => {
+                is_callable = false;
+                valid_unary_position = true;
 
-                    // Pop all operators until we find the matching open paren
-                    while *operators.last().unwrap() != Operator::Paren {
-                        consume_operator(&mut values, &mut operators, 0);
+            },
+
+            Token::Operator(mut op) => {
+
+                // Unary: Follows another operator or a left paren
+                if valid_unary_position {
+
+                    // Replace normal minus with its unary equivalant, a
+                    // unary plus has no effect at all
+                    match op {
+                        Operator::Minus => op = Operator::UnaryMinus,
+                        Operator::Plus => {
+                            last_token = token_type;
+                            continue;
+                        },
+                        Operator::UnaryNot | Operator::Negate => {},
+                        _ => return Err(error(
+                            ErrorCode::InvalidUnaryOperator,
+                            format!("Operator {:?} cannot be used as a unary operator", op),
+                            span
+                        ))
                     }
 
-                    // Closing paren needs to pop the open paren
-                    if token == Token::RParen {
+                // Binary: Follows an operand or a right paren
+                } else if is_unary(op) {
+                    return Err(error(
+                        ErrorCode::InvalidUnaryOperator,
+                        format!("Unary operator {:?} cannot be used as a binary operator", op),
+                        span
+                    ));
 
-                        // Pop open paren
-                        operators.pop();
+                } else {
+                    consume_operators(&mut values, &mut operators, op)?;
+                }
 
-                        // See if the topmost operator is a call and build a
-                        // call expression from it
-                        if match operators.last() {
-                            Some(op) => *op == Operator::Call,
-                            None => false
-                        } {
+                operators.push((op, span));
 
-                            let mut args: Vec<Expression> = vec![];
+                is_callable = false;
+                valid_unary_position = true;
 
-                            // First pop call operator
-                            operators.pop();
+            },
 
-                            // Then pop values until we find the name of the
-                            // function that is being called
-                            while values.len() > 0 {
+            Token::RParen | Token::Comma => {
 
-                                let value = values.pop().unwrap();
-                                match value {
+                // Arguments cannot be left out
+                if token == Token::Comma && (last_token == TokenType::Comma || last_token == TokenType::LParen) {
+                    return Err(error(ErrorCode::MissingOperand, "Expected argument before \",\"".to_string(), span));
 
-                                    // Found a name, so build a call expression
-                                    Expression::Name(name) => {
+                } else if token == Token::RParen && last_token == TokenType::Comma {
+                    return Err(error(ErrorCode::MissingOperand, "Expected argument after \",\"".to_string(), span));
+                }
 
-                                        // Reverse the argument list
-                                        args.reverse();
+                // Pop all operators until we find the matching open paren
+                loop {
+                    match operators.last() {
+                        Some(&(Operator::Paren, _)) => break,
+                        Some(_) => pop_operator(&mut values, &mut operators)?,
+                        None => return Err(error(
+                            ErrorCode::UnbalancedParenthesis,
+                            format!("Unexpected \"{}\" without a matching \"(\"", if token == Token::Comma { "," } else { ")" }),
+                            span
+                        ))
+                    }
+                }
 
-                                        values.push(Expression::Call(name, args));
-                                        break;
+                // Commas are only allowed to separate call arguments
+                let is_call = operators.len() > 1 && operators[operators.len() - 2].0 == Operator::Call;
+                if token == Token::Comma && !is_call {
+                    return Err(error(
+                        ErrorCode::UnexpectedToken,
+                        "Unexpected \",\" outside of a function call".to_string(),
+                        span
+                    ));
+                }
 
-                                    },
+                // Closing paren needs to pop the open paren
+                if token == Token::RParen {
 
-                                    // Any other value gets pushed into the arguments
-                                    _ => {
-                                        args.push(value);
-                                    }
+                    // Pop open paren
+                    operators.pop();
 
-                                }
+                    // See if the topmost operator is a call and build a
+                    // call expression from it
+                    if is_call {
 
-                            }
+                        // First pop call operator
+                        operators.pop();
 
+                        // Then collect all values which were pushed after
+                        // the name of the function that is being called
+                        let args = match calls.pop() {
+                            Some(start) if start <= values.len() => values.split_off(start),
+                            _ => return Err(error(ErrorCode::InvalidCall, "Invalid function call".to_string(), span))
+                        };
+                        match values.pop() {
+                            Some(Expression::Name(name, start)) => {
+                                values.push(Expression::Call(name, args, start.merge(span)));
+                            },
+                            _ => return Err(error(ErrorCode::InvalidCall, "Invalid function call".to_string(), span))
                         }
 
                     }
 
-                    is_callable = false;
-                    valid_unary_position = false;
+                }
 
-                },
+                // Arguments after a comma can start with an unary operator
+                is_callable = false;
+                valid_unary_position = token == Token::Comma;
 
-                _ => unreachable!()
+            },
 
+            token => {
+                return Err(error(
+                    ErrorCode::UnexpectedToken,
+                    format!("Unexpected {:?} in expression", token.to_type()),
+                    span
+                ));
             }
+
         }
 
-        // Assert correct final state of the algorithm
-        assert_eq!(operators.len(), 0);
-        assert_eq!(values.len(), 1);
+        last_token = token_type;
 
-        // The last remaining value is the final expression tree
-        values.pop().unwrap()
+    }
 
+    // Any remaining operator is a paren which was never closed
+    if let Some(&(_, span)) = operators.last() {
+        return Err(error(
+            ErrorCode::UnbalancedParenthesis,
+            "Unclosed \"(\" in expression".to_string(),
+            span
+        ));
+    }
+
+    // The last remaining value is the final expression tree
+    match values.len() {
+        1 => Ok(values.pop().unwrap()),
+        0 => Err(error(ErrorCode::MissingOperand, "Expected a value in expression".to_string(), end)),
+        _ => Err(error(
+            ErrorCode::UnexpectedToken,
+            "Expected operator between values".to_string(),
+            values[1].span()
+        ))
     }
 
 }
 
-fn consume_operator(values: &mut Vec<Expression>, operators: &mut Vec<Operator>, prec: i32) {
+fn is_unary(op: Operator) -> bool {
+    matches!(op, Operator::UnaryMinus | Operator::UnaryNot | Operator::Negate)
+}
 
-    // Check if the operator on top of the operator stack has a higher precedence
-    if match operators.last() {
-        // We leave equal precedence operators on the stack, this allows for right-associativity
-        Some(op) => op.get_prec() > prec,
+fn consume_operators(values: &mut Vec<Expression>, operators: &mut Vec<(Operator, Span)>, op: Operator) -> Result<(), Diagnostic> {
+
+    // Pop all operators from the stack which bind tighter than the new one,
+    // operators with the same precedence are left on the stack in case the
+    // new operator is right-associative
+    while match operators.last() {
+        Some(&(top, _)) => {
+            top.get_prec() > op.get_prec() || (
+                top.get_prec() == op.get_prec() && !op.is_right_associative()
+            )
+        },
         None => false
     } {
+        pop_operator(values, operators)?;
+    }
+
+    Ok(())
 
-        // Pop operator from stack
-        let op = operators.pop().unwrap();
+}
 
-        // Get the right hand side operand
-        let right = values.pop().unwrap();
+fn pop_operator(values: &mut Vec<Expression>, operators: &mut Vec<(Operator, Span)>) -> Result<(), Diagnostic> {
 
-        match op {
+    // Pop operator from stack
+    let (op, span) = match operators.pop() {
+        Some(operator) => operator,
+        None => return Ok(())
+    };
+
+    // Get the right hand side operand
+    let right = match values.pop() {
+        Some(right) => right,
+        None => return Err(missing_operand(op, span))
+    };
+
+    match op {
+
+        // Create a unary expression
+        Operator::UnaryMinus | Operator::UnaryNot | Operator::Negate => {
+            let span = span.merge(right.span());
+            values.push(Expression::Unary(op, Box::new(right), span));
+        },
+
+        // Create a conditional from the condition and both of its branches
+        Operator::ConditionalElse => {
+            let consequent = values.pop();
+            let condition = values.pop();
+            match (condition, consequent) {
+                (Some(condition), Some(consequent)) => {
+                    let span = condition.span().merge(right.span());
+                    values.push(Expression::Conditional(Box::new(condition), Box::new(consequent), Box::new(right), span));
+                },
+                _ => return Err(error(ErrorCode::IncompleteConditional, "Incomplete conditional expression".to_string(), span))
+            }
+        },
+
+        // A ? without a matching : is never valid
+        Operator::Conditional => {
+            return Err(error(ErrorCode::IncompleteConditional, "Expected \":\" after conditional expression".to_string(), span));
+        },
 
-            // Create a unary expression
-            Operator::UnaryMinus | Operator::UnaryNot => {
-                values.push(Expression::Unary(op, Box::new(right)));
+        // Create a binary expression by popping the left operand from
+        // the stack
+        _ => match values.pop() {
+            Some(left) => {
+                let span = left.span().merge(right.span());
+                values.push(Expression::Binary(op, Box::new(left), Box::new(right), span));
             },
+            None => return Err(missing_operand(op, span))
+        }
 
-            // Create a binary expression by popping the left operand from
-            // the stack, if there is no other operand than we have an invalid
-            // unary operation at hand
-            // TODO fail early by returning from the expression
-            // build when checking for unary expression types
-            _ => {
-                let operand = values.pop();
-                values.push(match operand {
-                    Some(left) => {
-                        Expression::Binary(op, Box::new(left), Box::new(right))
-                    },
-                    None => {
-                        Expression::Invalid("Invalid unary operator".to_string())
-                    }
-                });
+    };
+
+    Ok(())
+
+}
+
+fn missing_operand(op: Operator, span: Span) -> Diagnostic {
+    error(ErrorCode::MissingOperand, format!("Missing operand for operator {:?}", op), span)
+}
+
+fn call_function(name: &str, args: Vec<Expression>, span: Span) -> Expression {
+    match name {
+        "STRFMT" => match args.split_first() {
+            Some((Expression::String(format, _), args)) => match format_string(format, args, span) {
+                Ok(value) => Expression::String(value, span),
+                Err(diagnostic) => Expression::Invalid(diagnostic)
+            },
+            _ => invalid(ErrorCode::InvalidFormat, "STRFMT expects a format string as its first argument".to_string(), span)
+        },
+        _ => invalid(ErrorCode::UnknownFunction, format!("Unknown function \"{}\"", name), span)
+    }
+}
+
+fn evaluate_binary(op: Operator, left: Expression, right: Expression, span: Span) -> Expression {
+    match (left, right) {
+        (Expression::Number(a, _), Expression::Number(b, _)) => {
+            match op {
+                Operator::LogicalOr => bool_to_number(a != 0.0 || b != 0.0, span),
+                Operator::LogicalAnd => bool_to_number(a != 0.0 && b != 0.0, span),
+                Operator::BitwiseOr => Expression::Number((a as i32 | b as i32) as f32, span),
+                Operator::BitwiseXor => Expression::Number((a as i32 ^ b as i32) as f32, span),
+                Operator::BitwiseAnd => Expression::Number((a as i32 & b as i32) as f32, span),
+                Operator::Equal => bool_to_number(a == b, span),
+                Operator::NotEqual => bool_to_number(a != b, span),
+                Operator::LessThan => bool_to_number(a < b, span),
+                Operator::GreaterThan => bool_to_number(a > b, span),
+                Operator::LessThanEqual => bool_to_number(a <= b, span),
+                Operator::GreaterThanEqual => bool_to_number(a >= b, span),
+                Operator::ShiftLeft | Operator::ShiftRight => evaluate_shift(op, a, b, span),
+                Operator::Plus => Expression::Number(a + b, span),
+                Operator::Minus => Expression::Number(a - b, span),
+                Operator::Multiply => Expression::Number(a * b, span),
+                Operator::Power => Expression::Number(a.powf(b), span),
+                Operator::Divide | Operator::IntegerDivide | Operator::Modulo if b == 0.0 => {
+                    invalid(ErrorCode::DivisionByZero, "Division by zero".to_string(), span)
+                },
+                Operator::Divide => Expression::Number(a / b, span),
+                Operator::IntegerDivide => Expression::Number((a / b).floor(), span),
+                Operator::Modulo => Expression::Number(a % b, span),
+                _ => invalid(ErrorCode::InvalidOperands, format!("Invalid binary operation: {} {:?} {}", a, op, b), span)
             }
+        },
+        (Expression::String(a, _), Expression::String(b, _)) => {
+            match op {
+                Operator::Equal => bool_to_number(a == b, span),
+                Operator::NotEqual => bool_to_number(a != b, span),
+                Operator::Plus => Expression::String(a + &b, span),
+                _ => invalid(ErrorCode::InvalidOperands, format!("Invalid binary operation: \"{}\" {:?} \"{}\"", a, op, b), span)
+            }
+        },
+        (Expression::String(a, _), Expression::Number(b, _)) | (Expression::Number(b, _), Expression::String(a, _)) => {
+            match op {
+                Operator::Multiply if !b.is_finite() || b < 0.0 || a.len() as f32 * b > MAX_STRING_LENGTH as f32 => invalid(
+                    ErrorCode::InvalidOperands,
+                    format!("Invalid repeat count {} for \"{}\", the result must be between 0 and {} bytes long", b, a, MAX_STRING_LENGTH),
+                    span
+                ),
+                Operator::Multiply => Expression::String(a.repeat(b as usize), span),
+                _ => invalid(ErrorCode::InvalidOperands, format!("Invalid binary operation: \"{}\" {:?} {}", a, op, b), span)
+            }
+        },
+        (Expression::Invalid(diagnostic), _) | (_, Expression::Invalid(diagnostic)) => Expression::Invalid(diagnostic),
+        (_, _) => invalid(ErrorCode::InvalidOperands, format!("Invalid operands for binary operator {:?}", op), span)
+    }
+}
 
-        };
+fn evaluate_shift(op: Operator, a: f32, b: f32, span: Span) -> Expression {
+
+    let symbol = if op == Operator::ShiftLeft { "<<" } else { ">>" };
 
+    // Shift amounts must fit into the 32 bits of the shifted value
+    let result = if (0.0..32.0).contains(&b) {
+        match op {
+            Operator::ShiftLeft => (a as i32).checked_shl(b as u32),
+            _ => (a as i32).checked_shr(b as u32)
+        }
+
+    } else {
+        None
+    };
+
+    match result {
+        Some(value) => Expression::Number(value as f32, span),
+        None => invalid(
+            ErrorCode::InvalidOperands,
+            format!("Invalid shift amount {} for \"{}\", must be between 0 and 31", b, symbol),
+            span
+        )
+    }
+
+}
+
+fn evaluate_unary(op: Operator, right: Expression, span: Span) -> Expression {
+    match right {
+        Expression::Number(a, _) => {
+            match op {
+                Operator::UnaryMinus => Expression::Number(-a, span),
+                Operator::UnaryNot => bool_to_number(a == 0.0, span),
+                Operator::Negate => Expression::Number(!(a as i32) as f32, span),
+                _ => invalid(ErrorCode::InvalidOperands, format!("Invalid unary operation: {:?} {}", op, a), span)
+            }
+        },
+        Expression::Invalid(diagnostic) => Expression::Invalid(diagnostic),
+        _ => invalid(ErrorCode::InvalidOperands, format!("Invalid operand for unary operator {:?}", op), span)
     }
+}
 
+fn invalid(code: ErrorCode, message: String, span: Span) -> Expression {
+    Expression::Invalid(error(code, message, span))
+}
+
+fn error(code: ErrorCode, message: String, span: Span) -> Diagnostic {
+    Diagnostic::error(code, message, span)
+}
+
+fn bool_to_number(value: bool, span: Span) -> Expression {
+    if value {
+        Expression::Number(1.0, span)
+
+    } else {
+        Expression::Number(0.0, span)
+    }
 }
 
 /*
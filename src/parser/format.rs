@@ -0,0 +1,222 @@
+use compiler::{Diagnostic, ErrorCode};
+use parser::Expression;
+use parser::Span;
+
+/// The maximum field width and precision of a format specifier
+const MAX_FORMAT_SIZE: usize = 255;
+
+/// Formats the arguments according to a printf style format string
+///
+/// Supported conversions are %s, %d, %u, %x, %X, %o, %b and %f which can be
+/// combined with the "-" and "0" flags, a field width and a precision of up
+/// to 255 each.
+pub fn format_string(format: &str, args: &[Expression], span: Span) -> Result<String, Diagnostic> {
+
+    let mut output = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+
+        if ch != '%' {
+            output.push(ch);
+            continue;
+        }
+
+        // Escaped percent sign
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            output.push('%');
+            continue;
+        }
+
+        let mut spec = String::new();
+        loop {
+            match chars.next() {
+                Some(c) => {
+                    spec.push(c);
+                    if c.is_alphabetic() {
+                        break;
+                    }
+                },
+                None => return Err(error(ErrorCode::InvalidFormat, format!("Incomplete format specifier \"%{}\"", spec), span))
+            }
+        }
+
+        match args.next() {
+            Some(value) => output.push_str(&format_value(&spec, value, span)?),
+            None => return Err(error(ErrorCode::InvalidFormat, format!("Missing argument for format specifier \"%{}\"", spec), span))
+        }
+
+    }
+
+    if args.next().is_some() {
+        Err(error(ErrorCode::InvalidFormat, "Too many arguments for format string".to_string(), span))
+
+    } else {
+        Ok(output)
+    }
+
+}
+
+/// Replaces all {NAME} and {%SPEC:NAME} sequences in the text with the
+/// values of the named symbols, {{ and }} produce literal braces
+pub fn interpolate<F>(text: &str, resolve: &mut F, span: Span) -> Result<String, Diagnostic> where F: FnMut(&str) -> Option<Expression> {
+
+    if !text.contains('{') && !text.contains('}') {
+        return Ok(text.to_string());
+    }
+
+    let mut output = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+
+        if ch == '}' && chars.peek() == Some(&'}') {
+            chars.next();
+            output.push('}');
+
+        } else if ch != '{' {
+            output.push(ch);
+
+        } else if chars.peek() == Some(&'{') {
+            chars.next();
+            output.push('{');
+
+        } else {
+
+            // Collect the inner text, which may contain nested interpolations
+            let mut inner = String::new();
+            let mut depth = 0;
+            loop {
+                match chars.next() {
+                    Some('}') if depth == 0 => break,
+                    Some(c) => {
+                        if c == '{' {
+                            depth += 1;
+
+                        } else if c == '}' {
+                            depth -= 1;
+                        }
+                        inner.push(c);
+                    },
+                    None => return Err(error(ErrorCode::UnclosedInterpolation, format!("Unclosed interpolation \"{{{}\"", inner), span))
+                }
+            }
+
+            let (spec, name) = match inner.find(':') {
+                Some(index) if inner.starts_with('%') => (&inner[1..index], &inner[index + 1..]),
+                _ => ("s", &inner[..])
+            };
+
+            let name = interpolate(name.trim(), resolve, span)?;
+            match resolve(&name) {
+                Some(value) => output.push_str(&format_value(spec, &value, span)?),
+                None => return Err(error(ErrorCode::UndefinedName, format!("Undefined name \"{}\" in interpolation", name), span))
+            }
+
+        }
+
+    }
+
+    Ok(output)
+
+}
+
+
+// Helpers --------------------------------------------------------------------
+fn format_value(spec: &str, value: &Expression, span: Span) -> Result<String, Diagnostic> {
+
+    let conversion = match spec.chars().last() {
+        Some(c) if c.is_alphabetic() => c,
+        _ => return Err(error(ErrorCode::InvalidFormat, format!("Missing conversion in format specifier \"%{}\"", spec), span))
+    };
+    let mut flags = &spec[..spec.len() - conversion.len_utf8()];
+
+    let left_align = flags.starts_with('-');
+    if left_align {
+        flags = &flags[1..];
+    }
+
+    let zero_pad = flags.starts_with('0') && !left_align;
+    let (width, precision) = match flags.find('.') {
+        Some(index) => (&flags[..index], Some(&flags[index + 1..])),
+        None => (flags, None)
+    };
+
+    let width = match width {
+        "" => 0,
+        width => parse_size(width, "width", spec, span)?
+    };
+    let precision = match precision {
+        Some(p) => Some(parse_size(p, "precision", spec, span)?),
+        None => None
+    };
+
+    let text = match (conversion, value) {
+        ('s', Expression::String(s, _)) => s.clone(),
+        ('s', Expression::Number(n, _)) => number_to_string(*n),
+        ('d', Expression::Number(n, _)) | ('i', Expression::Number(n, _)) => format!("{}", *n as i64),
+        ('u', Expression::Number(n, _)) => format!("{}", *n as i64 as u32),
+        ('x', Expression::Number(n, _)) => format!("{:x}", *n as i64 as u32),
+        ('X', Expression::Number(n, _)) => format!("{:X}", *n as i64 as u32),
+        ('o', Expression::Number(n, _)) => format!("{:o}", *n as i64 as u32),
+        ('b', Expression::Number(n, _)) => format!("{:b}", *n as i64 as u32),
+        ('f', Expression::Number(n, _)) => format!("{:.*}", precision.unwrap_or(6), n),
+        (_, Expression::String(_, _)) | (_, Expression::Number(_, _)) => {
+            return Err(error(ErrorCode::InvalidFormat, format!("Invalid format specifier \"%{}\" for value {}", spec, value_to_string(value)), span));
+        },
+        _ => return Err(error(ErrorCode::InvalidFormat, "Only strings and numbers can be formatted".to_string(), span))
+    };
+
+    Ok(if text.len() >= width {
+        text
+
+    } else if left_align {
+        format!("{:<1$}", text, width)
+
+    } else if zero_pad && conversion != 's' {
+        match text.strip_prefix('-') {
+            Some(digits) => format!("-{:0>1$}", digits, width - 1),
+            None => format!("{:0>1$}", text, width)
+        }
+
+    } else {
+        format!("{:>1$}", text, width)
+    })
+
+}
+
+/// Parses the width or precision of a format specifier
+fn parse_size(size: &str, what: &str, spec: &str, span: Span) -> Result<usize, Diagnostic> {
+    match size.parse() {
+        Ok(size) if size <= MAX_FORMAT_SIZE => Ok(size),
+        Ok(_) => Err(error(
+            ErrorCode::InvalidFormat,
+            format!("The {} in format specifier \"%{}\" exceeds the maximum of {}", what, spec, MAX_FORMAT_SIZE),
+            span
+        )),
+        Err(_) => Err(error(ErrorCode::InvalidFormat, format!("Invalid {} in format specifier \"%{}\"", what, spec), span))
+    }
+}
+
+fn error(code: ErrorCode, message: String, span: Span) -> Diagnostic {
+    Diagnostic::error(code, message, span)
+}
+
+fn value_to_string(value: &Expression) -> String {
+    match *value {
+        Expression::String(ref s, _) => format!("\"{}\"", s),
+        Expression::Number(n, _) => number_to_string(n),
+        _ => "?".to_string()
+    }
+}
+
+fn number_to_string(value: f32) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value as i64)
+
+    } else {
+        format!("{}", value)
+    }
+}
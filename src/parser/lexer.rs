@@ -1,11 +1,15 @@
 use std::iter;
 
-use compiler::SourceIter;
+use compiler::{Diagnostic, ErrorCode, SourceIter};
+use parser::Span;
 use parser::Token;
 use parser::TokenType;
 use parser::Expression;
 use parser::BaseLexer;
 
+/// The maximum nesting depth of parentheses inside of an expression
+const MAX_PAREN_DEPTH: u8 = 64;
+
 /// Assembly Tokenizer which already builts expression trees
 pub struct Lexer<'a> {
     lexer: iter::Peekable<BaseLexer<'a>>,
@@ -27,7 +31,7 @@ impl <'a>Lexer<'a> {
         }
     }
 
-    fn next_token(&mut self) -> Token {
+    fn next_token(&mut self) -> (Token, Span) {
         self.lexer.next().unwrap()
     }
 
@@ -35,61 +39,57 @@ impl <'a>Lexer<'a> {
 
 impl<'a> Iterator for Lexer<'a> {
 
-    type Item = Token;
+    type Item = (Token, Span);
 
     fn next(&mut self) -> Option<Self::Item> {
 
-        let token = match self.next_token() {
+        let (token, span) = self.next_token();
+        let (token, span) = match token {
 
             // Combine offset labels with their argument
             Token::PositiveOffset => {
                 match self.next_token() {
-                    Token::Number(val) => Token::Offset(val as i32),
-                    _ => Token::Error("Expected number after offset sign".to_string())
+                    (Token::Number(val), end) => (Token::Offset(val as i32), span.merge(end)),
+                    (_, end) => (error(ErrorCode::ExpectedOffsetNumber, "Expected number after offset sign".to_string(), end), end)
                 }
             },
 
             Token::NegativeOffset => {
                 match self.next_token() {
-                    Token::Number(val) => Token::Offset(-(val as i32)),
-                    _ => Token::Error("Expected number after offset sign".to_string())
+                    (Token::Number(val), end) => (Token::Offset(-(val as i32)), span.merge(end)),
+                    (_, end) => (error(ErrorCode::ExpectedOffsetNumber, "Expected number after offset sign".to_string(), end), end)
                 }
             },
 
             // Disallow macro args outside of macro signatures and bodies
-            Token::MacroArg(name) => {
-                if !self.in_macro_args && !self.in_macro_body {
-                    Token::Error(format!("Unexpected MarcoArg @{} outside of marco arguments or macro body", name))
-
-                } else {
-                    Token::MacroArg(name.to_owned())
-                }
+            Token::MacroArg(name) if !self.in_macro_args && !self.in_macro_body => {
+                (error(ErrorCode::UnexpectedMacroArg, format!("Unexpected MarcoArg @{} outside of marco arguments or macro body", name), span), span)
             },
 
             // Combine macro tokens with their name
             Token::MacroDef => {
                 match self.next_token() {
-                    Token::Name(name) => {
+                    (Token::Name(name), end) => {
                         if self.in_macro_args {
-                            Token::Error("Already inside a MACRO arguments signature".to_string())
+                            (error(ErrorCode::NestedMacroArgs, "Already inside a MACRO arguments signature".to_string(), span), span)
 
                         } else {
                             self.in_macro_args = true;
-                            Token::Macro(name)
+                            (Token::Macro(name), span.merge(end))
                         }
                     },
-                    _ => Token::Error("Expected name after MARCO directive".to_string())
+                    (_, end) => (error(ErrorCode::ExpectedMacroName, "Expected name after MARCO directive".to_string(), end), end)
                 }
             },
 
             // End Macro bodies
             token @ Token::MacroEnd => {
                 if !self.in_macro_body {
-                    Token::Error("Unexpected MARCO_END directive outside of macro".to_string())
+                    (error(ErrorCode::UnexpectedMacroEnd, "Unexpected MARCO_END directive outside of macro".to_string(), span), span)
 
                 } else {
                     self.in_macro_body = false;
-                    token
+                    (token, span)
                 }
             },
 
@@ -102,7 +102,7 @@ impl<'a> Iterator for Lexer<'a> {
                         self.in_macro_args = false;
                         self.in_macro_body = true;
                     }
-                    token
+                    (token, span)
 
                 } else {
 
@@ -110,17 +110,27 @@ impl<'a> Iterator for Lexer<'a> {
                     // parenthesis for easier parsing
                     let mut token_type = token.to_type();
 
-                    if is_expression(self.last_token_type, token_type, self.paren_depth) {
+                    if is_expression_start(self.last_token_type, token_type, self.paren_depth) {
 
                         // Start expression stack
-                        let mut expression_stack = vec![Token::LParen, token];
+                        let mut end = span;
+                        let mut expression_stack = vec![
+                            (Token::LParen, span),
+                            (token, span)
+                        ];
+
+                        let mut too_deep = false;
 
                         loop {
 
                             // Handle parenthesis nesting
                             match token_type {
+                                TokenType::LParen if self.paren_depth == MAX_PAREN_DEPTH => {
+                                    too_deep = true;
+                                    break;
+                                },
                                 TokenType::LParen => self.paren_depth += 1,
-                                TokenType::RParen => self.paren_depth -= 1,
+                                TokenType::RParen => self.paren_depth = self.paren_depth.saturating_sub(1),
                                 _ => {}
                             };
 
@@ -129,13 +139,15 @@ impl<'a> Iterator for Lexer<'a> {
 
                             // Peek next token type
                             token_type = match self.lexer.peek() {
-                                Some(token) => token.to_type(),
+                                Some((token, _)) => token.to_type(),
                                 None => TokenType::Eof
                             };
 
                             // Check if the expression continues
                             if is_expression(self.last_token_type, token_type, self.paren_depth) {
-                                expression_stack.push(self.next_token());
+                                let (token, span) = self.next_token();
+                                end = span;
+                                expression_stack.push((token, span));
 
                             } else {
                                 break
@@ -143,11 +155,28 @@ impl<'a> Iterator for Lexer<'a> {
 
                         }
 
-                        expression_stack.push(Token::RParen);
-                        Token::Expression(Expression::new(expression_stack))
+                        // Skip the rest of the line so the error is only
+                        // reported once
+                        if too_deep {
+
+                            while !matches!(self.lexer.peek(), Some(&(Token::Newline, _)) | Some(&(Token::Eof, _)) | None) {
+                                self.lexer.next();
+                            }
+
+                            (error(
+                                ErrorCode::UnbalancedParenthesis,
+                                format!("Parentheses are nested deeper than {} levels", MAX_PAREN_DEPTH),
+                                end
+
+                            ), end)
+
+                        } else {
+                            expression_stack.push((Token::RParen, end));
+                            (Token::Expression(Expression::new(expression_stack)), span.merge(end))
+                        }
 
                     } else {
-                        token
+                        (token, span)
                     }
 
                 }
@@ -156,13 +185,51 @@ impl<'a> Iterator for Lexer<'a> {
 
         };
 
-        self.last_token_type = token.to_type();
-        Some(token)
+        // Unbalanced parenthesis must not leak into the next line
+        if token == Token::Newline {
+            self.paren_depth = 0;
+            self.last_token_type = TokenType::Begin;
+
+        } else {
+            self.last_token_type = token.to_type();
+        }
+
+        Some((token, span))
 
     }
 
 }
 
+fn error(code: ErrorCode, message: String, span: Span) -> Token {
+    Token::Error(Diagnostic::error(code, message, span))
+}
+
+fn is_expression_start(last: TokenType, next: TokenType, depth: u8) -> bool {
+    match last {
+
+        // Each comma separated value and each memory address starts a new
+        // expression
+        TokenType::Comma | TokenType::LBrace if depth == 0 => matches!(
+            next,
+            TokenType::LParen | TokenType::Name | TokenType::LocalLabelRef |
+            TokenType::Number | TokenType::String | TokenType::Operator |
+            TokenType::MacroArg
+        ),
+
+        // Names which are not part of a expression start a statement, the
+        // arguments of macro invocations follow them
+        TokenType::Name if depth == 0 => matches!(
+            next,
+            TokenType::LParen | TokenType::Name | TokenType::LocalLabelRef |
+            TokenType::Number | TokenType::String | TokenType::Operator |
+            TokenType::MacroArg
+        ),
+
+        _ => is_expression(last, next, depth)
+
+    }
+}
+
 fn is_expression(last: TokenType, next: TokenType, depth: u8) -> bool {
 
     match (last, next) {
@@ -180,6 +247,7 @@ fn is_expression(last: TokenType, next: TokenType, depth: u8) -> bool {
         (TokenType::LParen, TokenType::LParen) => true,
         (TokenType::LParen, TokenType::RParen) => true,
         (TokenType::LParen, TokenType::MacroArg) => true,
+        (TokenType::LParen, TokenType::Comma) => true,
 
         // Right Parenthesis
         (TokenType::RParen, TokenType::RParen) => true,
@@ -192,6 +260,7 @@ fn is_expression(last: TokenType, next: TokenType, depth: u8) -> bool {
         (TokenType::Operator, TokenType::LocalLabelRef) => true,
         (TokenType::Operator, TokenType::Name) => true,
         (TokenType::Operator, TokenType::MacroArg) => true,
+        (TokenType::Operator, TokenType::Operator) => true,
 
         // Numbers
         (TokenType::Number, TokenType::RParen) => true,
@@ -226,6 +295,10 @@ fn is_expression(last: TokenType, next: TokenType, depth: u8) -> bool {
         (TokenType::Comma, TokenType::String) => true,
         (TokenType::Comma, TokenType::Number) => true,
         (TokenType::Comma, TokenType::MacroArg) => true,
+        (TokenType::Comma, TokenType::LocalLabelRef) => true,
+        (TokenType::Comma, TokenType::Operator) => true,
+        (TokenType::Comma, TokenType::Comma) => true,
+        (TokenType::Comma, TokenType::RParen) => true,
 
         // Directive Values
         (TokenType::Directive, TokenType::LParen) => true,
@@ -233,6 +306,8 @@ fn is_expression(last: TokenType, next: TokenType, depth: u8) -> bool {
         (TokenType::Directive, TokenType::String) => true,
         (TokenType::Directive, TokenType::Number) => true,
         (TokenType::Directive, TokenType::MacroArg) => true,
+        (TokenType::Directive, TokenType::LocalLabelRef) => true,
+        (TokenType::Directive, TokenType::Operator) => true,
 
         // Instruction Values
         (TokenType::Instruction, TokenType::LParen) => true,
@@ -240,6 +315,8 @@ fn is_expression(last: TokenType, next: TokenType, depth: u8) -> bool {
         (TokenType::Instruction, TokenType::String) => true,
         (TokenType::Instruction, TokenType::Number) => true,
         (TokenType::Instruction, TokenType::MacroArg) => true,
+        (TokenType::Instruction, TokenType::LocalLabelRef) => true,
+        (TokenType::Instruction, TokenType::Operator) => true,
 
         // Everything else
         (_, _) => false
@@ -247,3 +324,58 @@ fn is_expression(last: TokenType, next: TokenType, depth: u8) -> bool {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+
+    use compiler::{ErrorCode, SourceString};
+    use parser::{Expression, Lexer, Token};
+
+    fn tokens(source: &str) -> Vec<Token> {
+        let mut source = SourceString::new("test", source);
+        Lexer::new(&mut source).map(|(token, _)| token).take_while(|t| *t != Token::Eof).collect()
+    }
+
+    fn errors(source: &str) -> Vec<(ErrorCode, usize)> {
+        tokens(source).into_iter().filter_map(|token| match token {
+            Token::Error(diagnostic) | Token::Expression(Expression::Invalid(diagnostic)) => {
+                Some((diagnostic.code, diagnostic.span.column))
+            },
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn test_unbalanced_parenthesis_reset_at_newline() {
+        let tokens = tokens("DB (1\nDB 1, 2");
+        let line = tokens.iter().position(|t| *t == Token::Newline).unwrap();
+        assert_eq!(tokens.len(), line + 5);
+        assert_eq!(tokens[line + 3], Token::Comma);
+    }
+
+    #[test]
+    fn test_unclosed_expression() {
+        assert_eq!(errors("DB (1 +"), vec![(ErrorCode::MissingOperand, 7)]);
+        assert_eq!(errors("DB (1"), vec![(ErrorCode::UnbalancedParenthesis, 4)]);
+    }
+
+    #[test]
+    fn test_dangling_operator() {
+        assert_eq!(errors("DB 1 +"), vec![(ErrorCode::MissingOperand, 6)]);
+        assert_eq!(errors("DB ()"), vec![(ErrorCode::MissingOperand, 5)]);
+    }
+
+    #[test]
+    fn test_invalid_unary_operator() {
+        assert_eq!(errors("DB 1 + * 2"), vec![(ErrorCode::InvalidUnaryOperator, 8)]);
+        assert_eq!(errors("DB 2 ~ 3"), vec![(ErrorCode::InvalidUnaryOperator, 6)]);
+    }
+
+    #[test]
+    fn test_parenthesis_nesting_limit() {
+        let source = format!("DB {}1\nDB 2, 3", "(".repeat(300));
+        assert_eq!(errors(&source), vec![(ErrorCode::UnbalancedParenthesis, 68)]);
+        assert_eq!(tokens(&source).iter().filter(|t| **t == Token::Comma).count(), 1);
+    }
+
+}
@@ -1,13 +1,17 @@
 pub use self::operator::Operator;
+pub use self::span::Span;
 pub use self::token::Token;
 pub use self::token::TokenType;
 pub use self::expression::Expression;
+pub use self::format::interpolate;
 pub use self::lexer::Lexer;
 pub use self::base_lexer::BaseLexer;
 
 mod operator;
+mod span;
 mod token;
 mod expression;
+mod format;
 mod base_lexer;
 mod lexer;
 
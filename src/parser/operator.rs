@@ -2,6 +2,8 @@
 pub enum Operator {
     Paren,
     Call,
+    Conditional,
+    ConditionalElse,
     LogicalOr,
     LogicalAnd,
     BitwiseOr,
@@ -34,31 +36,38 @@ impl Operator {
         match *self {
             Operator::Paren => 0,
             Operator::Call => 0,
-            Operator::LogicalOr => 1,
-            Operator::LogicalAnd => 2,
-            Operator::BitwiseOr => 3,
-            Operator::BitwiseXor => 4,
-            Operator::BitwiseAnd => 5,
-            Operator::Equal => 6,
-            Operator::NotEqual => 6,
-            Operator::LessThan => 7,
-            Operator::GreaterThan => 7,
-            Operator::LessThanEqual => 7,
-            Operator::GreaterThanEqual => 7,
-            Operator::ShiftLeft => 8,
-            Operator::ShiftRight => 8,
-            Operator::Plus => 9,
-            Operator::Minus => 9,
-            Operator::Negate => 9,
-            Operator::Multiply => 11,
-            Operator::Divide => 11,
-            Operator::IntegerDivide => 11,
-            Operator::Modulo => 11,
-            Operator::UnaryNot => 12,
-            Operator::UnaryMinus => 12,
-            Operator::Power => 13
+            Operator::Conditional => 1,
+            Operator::ConditionalElse => 1,
+            Operator::LogicalOr => 2,
+            Operator::LogicalAnd => 3,
+            Operator::BitwiseOr => 4,
+            Operator::BitwiseXor => 5,
+            Operator::BitwiseAnd => 6,
+            Operator::Equal => 7,
+            Operator::NotEqual => 7,
+            Operator::LessThan => 8,
+            Operator::GreaterThan => 8,
+            Operator::LessThanEqual => 8,
+            Operator::GreaterThanEqual => 8,
+            Operator::ShiftLeft => 9,
+            Operator::ShiftRight => 9,
+            Operator::Plus => 10,
+            Operator::Minus => 10,
+            Operator::Negate => 10,
+            Operator::Multiply => 12,
+            Operator::Divide => 12,
+            Operator::IntegerDivide => 12,
+            Operator::Modulo => 12,
+            Operator::UnaryNot => 13,
+            Operator::UnaryMinus => 13,
+            Operator::Power => 14
         }
     }
 
+    /// Returns whether operators of the same precedence group from the right
+    pub fn is_right_associative(&self) -> bool {
+        matches!(*self, Operator::Conditional | Operator::ConditionalElse | Operator::Power)
+    }
+
 }
 
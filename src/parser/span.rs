@@ -0,0 +1,45 @@
+/// The location of a token or expression within a source
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Span {
+
+    /// The ID of the source the location belongs to
+    pub file: usize,
+
+    /// The byte offset of the first character
+    pub start: usize,
+
+    /// The byte offset directly after the last character
+    pub end: usize,
+
+    /// The line of the first character, starting at 1
+    pub line: usize,
+
+    /// The column of the first character, starting at 1
+    pub column: usize
+
+}
+
+impl Span {
+
+    /// Returns a new span covering both this and the other span
+    pub fn merge(&self, other: Span) -> Span {
+        if other.start < self.start {
+            other.merge(*self)
+
+        } else {
+            Span {
+                end: if other.end > self.end { other.end } else { self.end },
+                .. *self
+            }
+        }
+    }
+
+    /// Returns a span which ends where the other span starts
+    pub fn until(&self, other: Span) -> Span {
+        Span {
+            end: other.start,
+            .. *self
+        }
+    }
+
+}
@@ -1,7 +1,8 @@
+use compiler::Diagnostic;
 use parser::Operator;
 use parser::Expression;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Newline,
     Whitespace,
@@ -17,7 +18,7 @@ pub enum Token {
     LocalLabelDef(String),
     LocalLabelRef(String),
     Offset(i32),
-    Error(String),
+    Error(Diagnostic),
     Macro(String),
     MacroArg(String),
     MacroDef,
@@ -66,7 +67,7 @@ impl Token {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum TokenType {
     Newline,
     Whitespace,